@@ -0,0 +1,177 @@
+use std::borrow::Cow;
+
+use mysql::Value;
+use regex::bytes::Regex;
+use serde::{Deserialize, Deserializer};
+
+use crate::TableInfo;
+
+/// A test of the values of a row, such as whether a transform applies to it.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Condition {
+    All { all: Vec<Condition> },
+    Any { any: Vec<Condition> },
+    Not { not: Box<Condition> },
+    Column(ColumnCondition),
+}
+
+/// Tests of a single column's value. Every test given must pass.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnCondition {
+    pub column: String,
+    pub eq: Option<Literal>,
+    pub ne: Option<Literal>,
+    #[serde(rename = "in")]
+    pub in_list: Option<Vec<Literal>>,
+    pub is_null: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub matches: Option<Regex>,
+}
+
+/// A value to compare a column with, as the text MySQL returns for it. Numbers and booleans may be
+/// written without quotes.
+#[derive(Deserialize, Debug)]
+#[serde(from = "Scalar")]
+pub struct Literal(String);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Scalar {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl From<Scalar> for Literal {
+    fn from(v: Scalar) -> Self {
+        match v {
+            Scalar::Bool(b) => Self(u8::from(b).to_string()),
+            Scalar::Int(n) => Self(n.to_string()),
+            Scalar::Float(n) => Self(n.to_string()),
+            Scalar::Str(s) => Self(s),
+        }
+    }
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) => Regex::new(&s).map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+impl Condition {
+    /// The names of the columns the condition tests.
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            Condition::All { all: conditions } | Condition::Any { any: conditions } => {
+                conditions.iter().flat_map(Condition::columns).collect()
+            }
+            Condition::Not { not } => not.columns(),
+            Condition::Column(c) => vec![c.column.as_str()],
+        }
+    }
+
+    pub fn matches(&self, info: &TableInfo, values: &[Value]) -> bool {
+        match self {
+            Condition::All { all } => all.iter().all(|c| c.matches(info, values)),
+            Condition::Any { any } => any.iter().any(|c| c.matches(info, values)),
+            Condition::Not { not } => !not.matches(info, values),
+            Condition::Column(c) => c.matches(info, values),
+        }
+    }
+}
+
+impl ColumnCondition {
+    fn matches(&self, info: &TableInfo, values: &[Value]) -> bool {
+        let value = &values[info.get_column_index(&self.column)];
+        let text = text(value);
+
+        let is = |literal: &Literal| text.as_deref() == Some(literal.0.as_bytes());
+
+        self.is_null.is_none_or(|n| n == text.is_none())
+            && self.eq.as_ref().is_none_or(is)
+            && self.ne.as_ref().is_none_or(|l| !is(l))
+            && self.in_list.as_ref().is_none_or(|list| list.iter().any(is))
+            && self
+                .matches
+                .as_ref()
+                .is_none_or(|re| text.as_deref().is_some_and(|t| re.is_match(t)))
+    }
+}
+
+/// The value as MySQL's text protocol returns it, `None` for NULL.
+fn text(value: &Value) -> Option<Cow<'_, [u8]>> {
+    let text = match value {
+        Value::NULL => return None,
+        Value::Bytes(b) => return Some(Cow::Borrowed(b)),
+        Value::Int(n) => n.to_string(),
+        Value::UInt(n) => n.to_string(),
+        Value::Float(n) => n.to_string(),
+        Value::Double(n) => n.to_string(),
+        v => v.as_sql(true).trim_matches('\'').to_string(),
+    };
+
+    Some(Cow::Owned(text.into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mysql::consts::ColumnType::{MYSQL_TYPE_LONG, MYSQL_TYPE_VAR_STRING};
+
+    fn condition(yaml: &str) -> Condition {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn lists_the_columns_tested() {
+        let c = condition(
+            "
+            all:
+              - {column: country, in: [DE, FR]}
+              - any:
+                - {column: is_test, eq: true}
+                - not: {column: email, matches: '@example\\.com$'}
+            ",
+        );
+
+        assert_eq!(c.columns(), ["country", "is_test", "email"]);
+    }
+
+    #[test]
+    fn matches_row_values() {
+        let info = TableInfo::with_columns(
+            "app",
+            "users",
+            &[
+                ("is_test", MYSQL_TYPE_LONG),
+                ("email", MYSQL_TYPE_VAR_STRING),
+            ],
+        );
+        let c = condition(
+            "
+            any:
+              - {column: is_test, eq: true}
+              - {column: email, matches: '@example\\.com$'}
+            ",
+        );
+        let row = |is_test: &str, email: Option<&str>| {
+            vec![
+                Value::Bytes(is_test.into()),
+                email.map_or(Value::NULL, |e| Value::Bytes(e.into())),
+            ]
+        };
+
+        assert!(c.matches(&info, &row("1", Some("a@b.org"))));
+        assert!(c.matches(&info, &row("0", Some("a@example.com"))));
+        assert!(!c.matches(&info, &row("0", Some("a@b.org"))));
+        assert!(!c.matches(&info, &row("0", None)));
+    }
+}
@@ -0,0 +1,361 @@
+use std::ops::RangeInclusive;
+
+use mysql::consts::ColumnType;
+use mysql::Value;
+
+/// A DATE, DATETIME or TIMESTAMP value, remembering how it was read so it can be written back the
+/// same way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DateTime {
+    /// Days since 1970-01-01.
+    days: i64,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    micros: u32,
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    /// `YYYY-MM-DD`
+    Date,
+    /// `YYYY-MM-DD HH:MM:SS` with the number of fractional second digits.
+    DateTime(usize),
+    /// `mysql::Value::Date`
+    Binary,
+}
+
+impl DateTime {
+    /// The date of a value read from the database. `None` for values that aren't dates, and for
+    /// zero dates which have no place on the calendar.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Date(year, month, day, hour, minute, second, micros) => {
+                Self::new(*year as i64, *month, *day, Format::Binary).map(|d| Self {
+                    hour: *hour,
+                    minute: *minute,
+                    second: *second,
+                    micros: *micros,
+                    ..d
+                })
+            }
+            Value::Bytes(b) => std::str::from_utf8(b).ok().and_then(Self::parse),
+            _ => None,
+        }
+    }
+
+    /// A date in the `YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS[.ffffff]` formats.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (date, time) = match s.split_once(' ') {
+            Some((date, time)) => (date, Some(time)),
+            None => (s, None),
+        };
+
+        let mut parts = date.splitn(3, '-');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let day = parts.next()?.parse().ok()?;
+
+        let time = match time {
+            None => return Self::new(year, month, day, Format::Date),
+            Some(time) => time,
+        };
+
+        let (time, fraction) = match time.split_once('.') {
+            Some((time, fraction)) => (time, fraction),
+            None => (time, ""),
+        };
+
+        let mut parts = time.splitn(3, ':');
+        let hour = parts.next()?.parse().ok()?;
+        let minute = parts.next()?.parse().ok()?;
+        let second = parts.next()?.parse().ok()?;
+        let micros = match fraction {
+            "" => 0,
+            f if f.len() <= 6 => format!("{f:0<6}").parse().ok()?,
+            _ => return None,
+        };
+
+        Self::new(year, month, day, Format::DateTime(fraction.len())).map(|d| Self {
+            hour,
+            minute,
+            second,
+            micros,
+            ..d
+        })
+    }
+
+    fn new(year: i64, month: u8, day: u8, format: Format) -> Option<Self> {
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+
+        Some(Self {
+            days: days_from_civil(year, month, day),
+            hour: 0,
+            minute: 0,
+            second: 0,
+            micros: 0,
+            format,
+        })
+    }
+
+    /// The value as it was read, with the new date and time.
+    pub fn to_value(self) -> Value {
+        let (year, month, day) = civil_from_days(self.days);
+
+        match self.format {
+            Format::Binary => Value::Date(
+                year as u16,
+                month,
+                day,
+                self.hour,
+                self.minute,
+                self.second,
+                self.micros,
+            ),
+            Format::Date => Value::Bytes(format!("{year:04}-{month:02}-{day:02}").into_bytes()),
+            Format::DateTime(digits) => {
+                let mut s = format!(
+                    "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+                    self.hour, self.minute, self.second
+                );
+                if digits > 0 {
+                    s.push_str(&format!(".{:06}", self.micros)[..digits + 1]);
+                }
+                Value::Bytes(s.into_bytes())
+            }
+        }
+    }
+
+    pub fn days(&self) -> i64 {
+        self.days
+    }
+
+    /// The same time of day on another day. Days are kept within years 1 to 9999.
+    pub fn with_days(self, days: i64) -> Self {
+        Self {
+            days: days.clamp(days_from_civil(1, 1, 1), days_from_civil(9999, 12, 31)),
+            ..self
+        }
+    }
+
+    /// The same time of day on the nearest of the days.
+    pub fn within(self, days: &RangeInclusive<i64>) -> Self {
+        Self {
+            days: self.days.clamp(*days.start(), *days.end()),
+            ..self
+        }
+    }
+
+    /// The first moment of the year, month or day.
+    pub fn truncate(self, unit: DateUnit) -> Self {
+        let (year, month, day) = civil_from_days(self.days);
+        let days = match unit {
+            DateUnit::Year => days_from_civil(year, 1, 1),
+            DateUnit::Month => days_from_civil(year, month, 1),
+            DateUnit::Day => days_from_civil(year, month, day),
+        };
+
+        Self {
+            days,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            micros: 0,
+            format: self.format,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateUnit {
+    Year,
+    Month,
+    Day,
+}
+
+impl std::str::FromStr for DateUnit {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "year" => Ok(Self::Year),
+            "month" => Ok(Self::Month),
+            "day" => Ok(Self::Day),
+            _ => Err(color_eyre::eyre::eyre!(
+                "unknown date unit {s}, expected year, month or day"
+            )),
+        }
+    }
+}
+
+/// The days a column of the type can hold, for the types holding dates. TIMESTAMP's limits are in
+/// UTC while values are read in the session's time zone, so its first and last days are left out.
+pub fn column_days(column_type: ColumnType) -> Option<RangeInclusive<i64>> {
+    use mysql::consts::ColumnType::*;
+
+    match column_type {
+        MYSQL_TYPE_DATE | MYSQL_TYPE_NEWDATE | MYSQL_TYPE_DATETIME | MYSQL_TYPE_DATETIME2 => {
+            Some(days_from_civil(1000, 1, 1)..=days_from_civil(9999, 12, 31))
+        }
+        MYSQL_TYPE_TIMESTAMP | MYSQL_TYPE_TIMESTAMP2 => {
+            Some(days_from_civil(1970, 1, 2)..=days_from_civil(2038, 1, 17))
+        }
+        _ => None,
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Conversions between days and the proleptic Gregorian calendar, from
+// http://howardhinnant.github.io/date_algorithms.html
+
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: Value) -> String {
+        match value {
+            Value::Bytes(b) => String::from_utf8(b).unwrap(),
+            v => panic!("not text: {v:?}"),
+        }
+    }
+
+    #[test]
+    fn converts_days_to_and_from_the_calendar() {
+        for (days, civil) in [
+            (0, (1970, 1, 1)),
+            (-1, (1969, 12, 31)),
+            (59, (1970, 3, 1)),
+            (11016, (2000, 2, 29)),
+            (11017, (2000, 3, 1)),
+            (-719162, (1, 1, 1)),
+            (2932896, (9999, 12, 31)),
+        ] {
+            assert_eq!(
+                days_from_civil(civil.0, civil.1, civil.2),
+                days,
+                "{civil:?}"
+            );
+            assert_eq!(civil_from_days(days), civil, "{days}");
+        }
+
+        for days in (-719162..2932897).step_by(97) {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+    }
+
+    #[test]
+    fn knows_leap_years() {
+        assert_eq!(days_in_month(2000, 2), 29);
+        assert_eq!(days_in_month(1900, 2), 28);
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(2023, 2), 28);
+    }
+
+    #[test]
+    fn writes_values_back_as_they_were_read() {
+        for s in [
+            "2024-02-29",
+            "1999-12-31 23:59:59",
+            "2001-01-01 00:00:00.5",
+            "2001-01-01 00:00:00.000123",
+        ] {
+            let date = DateTime::from_value(&Value::Bytes(s.into())).unwrap();
+            assert_eq!(text(date.to_value()), s);
+        }
+
+        let binary = Value::Date(2024, 2, 29, 13, 14, 15, 16);
+        assert_eq!(DateTime::from_value(&binary).unwrap().to_value(), binary);
+    }
+
+    #[test]
+    fn rejects_values_which_are_not_dates() {
+        for s in [
+            "0000-00-00",
+            "2023-02-29",
+            "2023-13-01",
+            "2023-01-01 00:00:00.1234567",
+            "x",
+        ] {
+            assert_eq!(DateTime::parse(s), None, "{s}");
+        }
+    }
+
+    #[test]
+    fn moves_and_truncates_keeping_the_format() {
+        let date = DateTime::parse("2024-03-15 10:20:30").unwrap();
+
+        assert_eq!(
+            text(date.with_days(date.days() - 15).to_value()),
+            "2024-02-29 10:20:30"
+        );
+        assert_eq!(
+            text(date.with_days(i64::MAX).to_value()),
+            "9999-12-31 10:20:30"
+        );
+        assert_eq!(
+            text(date.truncate(DateUnit::Year).to_value()),
+            "2024-01-01 00:00:00"
+        );
+        assert_eq!(
+            text(date.truncate(DateUnit::Month).to_value()),
+            "2024-03-01 00:00:00"
+        );
+    }
+
+    #[test]
+    fn keeps_dates_within_the_column_type() {
+        use mysql::consts::ColumnType::*;
+
+        let timestamp = column_days(MYSQL_TYPE_TIMESTAMP).unwrap();
+        let datetime = column_days(MYSQL_TYPE_DATETIME).unwrap();
+        let early = DateTime::parse("1969-07-20 20:17:40").unwrap();
+        let late = DateTime::parse("2040-01-01").unwrap();
+
+        assert_eq!(
+            text(early.within(&timestamp).to_value()),
+            "1970-01-02 20:17:40"
+        );
+        assert_eq!(text(late.within(&timestamp).to_value()), "2038-01-17");
+        assert_eq!(early.within(&datetime), early);
+        assert_eq!(column_days(MYSQL_TYPE_VAR_STRING), None);
+    }
+}
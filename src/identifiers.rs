@@ -0,0 +1,320 @@
+use rand::Rng;
+
+/// Digits of a card number kept as they are; the issuer identification number.
+const CARD_BIN_DIGITS: usize = 6;
+
+/// Digits of a card number generated for a value which isn't shaped like one.
+const CARD_DIGITS: usize = 16;
+
+/// Length of an IBAN generated for a value which isn't shaped like one; that of a German IBAN.
+const IBAN_LENGTH: usize = 22;
+
+/// A card number with the same issuer, length and separators, random account digits and a valid
+/// Luhn check digit. A value with too few digits to be a card number is replaced by a random
+/// 16 digit card number.
+pub fn credit_card(rng: &mut impl Rng, original: &str) -> String {
+    let positions: Vec<usize> = original
+        .char_indices()
+        .filter(|(_, c)| c.is_ascii_digit())
+        .map(|(i, _)| i)
+        .collect();
+
+    if positions.len() <= CARD_BIN_DIGITS + 1 {
+        return random_credit_card(rng);
+    }
+
+    let mut digits: Vec<u8> = positions
+        .iter()
+        .map(|i| original.as_bytes()[*i] - b'0')
+        .collect();
+
+    let check = digits.len() - 1;
+    for d in &mut digits[CARD_BIN_DIGITS..check] {
+        *d = rng.gen_range(0..10);
+    }
+    digits[check] = luhn_check_digit(&digits[..check]);
+
+    let mut card = original.as_bytes().to_vec();
+    for (i, d) in positions.iter().zip(digits) {
+        card[*i] = b'0' + d;
+    }
+
+    String::from_utf8(card).expect("only ascii digits were replaced")
+}
+
+/// A random 16 digit card number of a Visa shaped issuer with a valid Luhn check digit.
+pub fn random_credit_card(rng: &mut impl Rng) -> String {
+    let mut digits = vec![4];
+    digits.extend((1..CARD_DIGITS - 1).map(|_| rng.gen_range(0..10)));
+    digits.push(luhn_check_digit(&digits));
+
+    digits.into_iter().map(|d| char::from(b'0' + d)).collect()
+}
+
+/// The digit which makes the digits followed by it pass the Luhn check.
+fn luhn_check_digit(digits: &[u8]) -> u8 {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| match i % 2 {
+            0 if *d >= 5 => (*d as u32) * 2 - 9,
+            0 => (*d as u32) * 2,
+            _ => *d as u32,
+        })
+        .sum();
+
+    ((10 - sum % 10) % 10) as u8
+}
+
+/// A social security number shaped value; area 001-899 other than 666, group 01-99 and serial
+/// 0001-9999. Dashed when the original is.
+pub fn ssn(rng: &mut impl Rng, original: &str) -> String {
+    let area = match rng.gen_range(1..900) {
+        666 => 667,
+        n => n,
+    };
+    let group = rng.gen_range(1..100);
+    let serial = rng.gen_range(1..10000);
+
+    match original.contains('-') {
+        true => format!("{area:03}-{group:02}-{serial:04}"),
+        false => format!("{area:03}{group:02}{serial:04}"),
+    }
+}
+
+/// An IBAN with the same country, length and separators, such as spaces or dashes, a random
+/// account of the same letters and digits and valid check digits. A value which isn't shaped like
+/// an IBAN is replaced by a random German IBAN.
+pub fn iban(rng: &mut impl Rng, original: &str) -> String {
+    let is_separator = |c: &char| !c.is_alphanumeric();
+    let compact: Vec<char> = original.chars().filter(|c| !is_separator(c)).collect();
+
+    if compact.len() < 5
+        || !compact[..2].iter().all(char::is_ascii_alphabetic)
+        || !compact.iter().all(char::is_ascii_alphanumeric)
+    {
+        return random_iban(rng);
+    }
+
+    let country: String = compact[..2].iter().collect::<String>().to_ascii_uppercase();
+    let account: String = compact[4..]
+        .iter()
+        .map(|c| match c {
+            c if c.is_ascii_digit() => char::from(b'0' + rng.gen_range(0..10)),
+            c if c.is_ascii_lowercase() => char::from(b'a' + rng.gen_range(0..26)),
+            _ => char::from(b'A' + rng.gen_range(0..26)),
+        })
+        .collect();
+
+    let check = 98 - iban_mod97(&format!("{account}{country}00"));
+    let iban: Vec<char> = format!("{country}{check:02}{account}").chars().collect();
+
+    // Put the original's separators back.
+    let mut chars = iban.into_iter();
+    original
+        .chars()
+        .map(|c| match is_separator(&c) {
+            true => c,
+            false => chars.next().unwrap_or(c),
+        })
+        .collect()
+}
+
+/// A random German IBAN with valid check digits.
+pub fn random_iban(rng: &mut impl Rng) -> String {
+    let account: String = (4..IBAN_LENGTH)
+        .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+        .collect();
+    let check = 98 - iban_mod97(&format!("{account}DE00"));
+
+    format!("DE{check:02}{account}")
+}
+
+/// The remainder of dividing the number made by replacing each letter with two digits, A as 10
+/// to Z as 35, by 97.
+fn iban_mod97(s: &str) -> u32 {
+    s.chars().fold(0, |rem, c| {
+        let n = c.to_digit(36).expect("alphanumeric");
+        match n {
+            0..=9 => (rem * 10 + n) % 97,
+            _ => (rem * 100 + n) % 97,
+        }
+    })
+}
+
+/// A random version 4 UUID in the same form as the original; 16 bytes as in a BINARY(16) column,
+/// 32 hex digits, or hex with dashes, in the original's case.
+pub fn uuid(rng: &mut impl Rng, original: &[u8]) -> Vec<u8> {
+    let mut bytes: [u8; 16] = rng.gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    if original.len() == 16 {
+        return bytes.to_vec();
+    }
+
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    let uuid = match original.contains(&b'-') {
+        true => format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        ),
+        false => hex,
+    };
+
+    match original.iter().any(u8::is_ascii_uppercase) {
+        true => uuid.to_ascii_uppercase().into_bytes(),
+        false => uuid.into_bytes(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(0)
+    }
+
+    fn digits(s: &str) -> Vec<u8> {
+        s.bytes()
+            .filter(u8::is_ascii_digit)
+            .map(|b| b - b'0')
+            .collect()
+    }
+
+    fn passes_luhn(s: &str) -> bool {
+        let digits = digits(s);
+        let (check, rest) = digits.split_last().unwrap();
+        luhn_check_digit(rest) == *check
+    }
+
+    fn is_valid_iban(s: &str) -> bool {
+        let compact: String = s.chars().filter(char::is_ascii_alphanumeric).collect();
+        iban_mod97(&format!("{}{}", &compact[4..], &compact[..4])) == 1
+    }
+
+    #[test]
+    fn computes_luhn_check_digits() {
+        assert!(passes_luhn("4111 1111 1111 1111"));
+        assert!(passes_luhn("5555555555554444"));
+        assert!(passes_luhn("378282246310005"));
+        assert!(!passes_luhn("4111111111111112"));
+    }
+
+    #[test]
+    fn keeps_the_issuer_and_shape_of_card_numbers() {
+        let mut rng = rng();
+        for original in [
+            "4111-1111-1111-1111",
+            "378282246310005",
+            "5555 5555 5555 4444",
+        ] {
+            let card = credit_card(&mut rng, original);
+            assert_eq!(card.len(), original.len());
+            assert_eq!(
+                card[..7].replace([' ', '-'], ""),
+                original[..7].replace([' ', '-'], "")
+            );
+            assert_eq!(
+                card.replace(|c: char| c.is_ascii_digit(), "0"),
+                original.replace(|c: char| c.is_ascii_digit(), "0")
+            );
+            assert_ne!(card, original);
+            assert!(passes_luhn(&card), "{card}");
+        }
+    }
+
+    #[test]
+    fn replaces_values_which_are_not_card_numbers() {
+        let mut rng = rng();
+        for original in ["1234567", "n/a", ""] {
+            let card = credit_card(&mut rng, original);
+            assert_eq!(card.len(), 16);
+            assert!(card.starts_with('4'));
+            assert!(passes_luhn(&card), "{card}");
+        }
+    }
+
+    #[test]
+    fn generates_ssns_in_range() {
+        let mut rng = rng();
+        for _ in 0..1000 {
+            let ssn = ssn(&mut rng, "123-45-6789");
+            let parts: Vec<u32> = ssn.split('-').map(|p| p.parse().unwrap()).collect();
+            assert!((1..=899).contains(&parts[0]) && parts[0] != 666, "{ssn}");
+            assert!((1..=99).contains(&parts[1]), "{ssn}");
+            assert!((1..=9999).contains(&parts[2]), "{ssn}");
+        }
+        assert_eq!(ssn(&mut rng, "123456789").len(), 9);
+    }
+
+    #[test]
+    fn computes_iban_check_digits() {
+        assert!(is_valid_iban("DE89 3704 0044 0532 0130 00"));
+        assert!(is_valid_iban("GB82WEST12345698765432"));
+        assert!(!is_valid_iban("GB82WEST12345698765433"));
+    }
+
+    #[test]
+    fn keeps_the_country_and_shape_of_ibans() {
+        let mut rng = rng();
+        for original in [
+            "DE89 3704 0044 0532 0130 00",
+            "DE89-3704-0044-0532-0130-00",
+            "GB82WEST12345698765432",
+        ] {
+            let iban = iban(&mut rng, original);
+            assert_eq!(iban.len(), original.len());
+            assert_eq!(iban[..2], original[..2]);
+            assert_eq!(
+                iban.replace(|c: char| c.is_ascii_digit(), "0")
+                    .replace(|c: char| c.is_ascii_uppercase(), "A"),
+                original
+                    .replace(|c: char| c.is_ascii_digit(), "0")
+                    .replace(|c: char| c.is_ascii_uppercase(), "A"),
+            );
+            assert_ne!(iban, original);
+            assert!(is_valid_iban(&iban), "{iban}");
+        }
+    }
+
+    #[test]
+    fn replaces_values_which_are_not_ibans() {
+        let mut rng = rng();
+        for original in ["1234", "12 3456 7890", "n/a"] {
+            let iban = iban(&mut rng, original);
+            assert_eq!(iban.len(), IBAN_LENGTH);
+            assert!(iban.starts_with("DE"));
+            assert!(is_valid_iban(&iban), "{iban}");
+        }
+    }
+
+    #[test]
+    fn generates_version_4_uuids_like_the_original() {
+        let mut rng = rng();
+
+        let binary = uuid(&mut rng, &[0; 16]);
+        assert_eq!(binary.len(), 16);
+        assert_eq!(binary[6] >> 4, 4);
+        assert_eq!(binary[8] >> 6, 0b10);
+
+        let dashed =
+            String::from_utf8(uuid(&mut rng, b"123e4567-e89b-12d3-a456-426614174000")).unwrap();
+        assert_eq!(dashed.len(), 36);
+        assert_eq!(dashed.as_bytes()[14], b'4');
+        assert!(matches!(dashed.as_bytes()[19], b'8' | b'9' | b'a' | b'b'));
+        assert_eq!(dashed, dashed.to_ascii_lowercase());
+
+        let upper = String::from_utf8(uuid(&mut rng, b"123E4567E89B12D3A456426614174000")).unwrap();
+        assert_eq!(upper.len(), 32);
+        assert_eq!(upper, upper.to_ascii_uppercase());
+    }
+}
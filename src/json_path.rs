@@ -0,0 +1,301 @@
+use color_eyre::eyre::{eyre, Report};
+use mysql::Value;
+use serde::Deserialize;
+use serde_json::Value as Json;
+
+/// A path to values inside a JSON document, in the subset of MySQL's path syntax made of `$`
+/// followed by `.member`, `."quoted member"`, `.*`, `[n]`, `[*]` and `**` for any depth.
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "String")]
+pub struct JsonPath(Vec<Segment>);
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Member(String),
+    AnyMember,
+    Index(usize),
+    AnyIndex,
+    AnyDepth,
+}
+
+impl TryFrom<String> for JsonPath {
+    type Error = Report;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let invalid = || eyre!("invalid JSON path {s}");
+
+        let mut rest = s.strip_prefix('$').ok_or_else(invalid)?;
+        let mut segments = Vec::new();
+
+        while !rest.is_empty() {
+            if let Some(r) = rest.strip_prefix("**") {
+                segments.push(Segment::AnyDepth);
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix(".*") {
+                segments.push(Segment::AnyMember);
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix(".\"") {
+                let end = r.find('"').ok_or_else(invalid)?;
+                segments.push(Segment::Member(r[..end].to_owned()));
+                rest = &r[end + 1..];
+            } else if let Some(r) = rest.strip_prefix('.') {
+                let end = r.find(['.', '[', '*']).unwrap_or(r.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                segments.push(Segment::Member(r[..end].to_owned()));
+                rest = &r[end..];
+            } else if let Some(r) = rest.strip_prefix('[') {
+                let end = r.find(']').ok_or_else(invalid)?;
+                segments.push(match r[..end].trim() {
+                    "*" => Segment::AnyIndex,
+                    n => Segment::Index(n.parse().map_err(|_| invalid())?),
+                });
+                rest = &r[end + 1..];
+            } else {
+                return Err(invalid());
+            }
+        }
+
+        // Like MySQL, a path can't end in `**` as it would match every value.
+        if segments.last() == Some(&Segment::AnyDepth) {
+            return Err(invalid());
+        }
+
+        Ok(Self(segments))
+    }
+}
+
+impl JsonPath {
+    /// Call `f` with each string, number, boolean or null the path matches in the JSON document,
+    /// then write the document back. Objects and arrays the path matches are left as they are.
+    /// Values which are not JSON documents are left unchanged. Stops at the first error of `f`.
+    pub fn apply(
+        &self,
+        value: &mut Value,
+        mut f: impl FnMut(&mut Value) -> Result<(), Report>,
+    ) -> Result<(), Report> {
+        let mut doc: Json = match value {
+            Value::Bytes(b) => match serde_json::from_slice(b) {
+                Ok(doc) => doc,
+                Err(_) => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+
+        let mut result = Ok(());
+        visit(&self.0, &mut doc, &mut |leaf| {
+            if result.is_ok() {
+                let mut v = to_mysql(leaf);
+                result = f(&mut v);
+                *leaf = to_json(leaf, v);
+            }
+        });
+        result?;
+
+        *value = Value::Bytes(doc.to_string().into_bytes());
+        Ok(())
+    }
+}
+
+fn visit(segments: &[Segment], node: &mut Json, f: &mut impl FnMut(&mut Json)) {
+    let (segment, rest) = match segments.split_first() {
+        Some(s) => s,
+        None => {
+            if !node.is_object() && !node.is_array() {
+                f(node);
+            }
+            return;
+        }
+    };
+
+    match (segment, node) {
+        (Segment::Member(name), Json::Object(map)) => {
+            if let Some(child) = map.get_mut(name) {
+                visit(rest, child, f);
+            }
+        }
+        (Segment::AnyMember, Json::Object(map)) => {
+            for child in map.values_mut() {
+                visit(rest, child, f);
+            }
+        }
+        (Segment::Index(n), Json::Array(items)) => {
+            if let Some(child) = items.get_mut(*n) {
+                visit(rest, child, f);
+            }
+        }
+        (Segment::AnyIndex, Json::Array(items)) => {
+            for child in items.iter_mut() {
+                visit(rest, child, f);
+            }
+        }
+        (Segment::AnyDepth, node) => {
+            visit(rest, node, f);
+            match node {
+                Json::Object(map) => map.values_mut().for_each(|c| visit(segments, c, f)),
+                Json::Array(items) => items.iter_mut().for_each(|c| visit(segments, c, f)),
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+fn to_mysql(leaf: &Json) -> Value {
+    match leaf {
+        Json::Null => Value::NULL,
+        Json::String(s) => Value::Bytes(s.as_bytes().to_vec()),
+        other => Value::Bytes(other.to_string().into_bytes()),
+    }
+}
+
+/// The transformed value as JSON, keeping the type of the original where the new value allows.
+fn to_json(original: &Json, value: Value) -> Json {
+    let text = match value {
+        Value::NULL => return Json::Null,
+        Value::Bytes(b) => String::from_utf8_lossy(&b).into_owned(),
+        Value::Int(n) => return n.into(),
+        Value::UInt(n) => return n.into(),
+        Value::Double(n) => return n.into(),
+        Value::Float(n) => return n.into(),
+        other => other.as_sql(true).trim_matches('\'').to_owned(),
+    };
+
+    match original {
+        Json::Number(_) | Json::Bool(_) => match serde_json::from_str(&text) {
+            Ok(json @ (Json::Number(_) | Json::Bool(_))) => json,
+            _ => Json::String(text),
+        },
+        _ => Json::String(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(s: &str) -> Result<JsonPath, Report> {
+        JsonPath::try_from(s.to_owned())
+    }
+
+    fn segments(s: &str) -> Vec<Segment> {
+        path(s).unwrap().0
+    }
+
+    fn apply(p: &str, doc: &str, f: impl FnMut(&mut Value) -> Result<(), Report>) -> String {
+        let mut value = Value::Bytes(doc.as_bytes().to_vec());
+        path(p).unwrap().apply(&mut value, f).unwrap();
+        match value {
+            Value::Bytes(b) => String::from_utf8(b).unwrap(),
+            v => panic!("not bytes: {v:?}"),
+        }
+    }
+
+    fn replace(text: &str) -> impl FnMut(&mut Value) -> Result<(), Report> + '_ {
+        |v| {
+            *v = Value::Bytes(text.as_bytes().to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parses_paths() {
+        use Segment::*;
+
+        assert_eq!(segments("$"), []);
+        assert_eq!(
+            segments("$.contact.email"),
+            [Member("contact".into()), Member("email".into())]
+        );
+        assert_eq!(
+            segments("$.\"first.name\".x"),
+            [Member("first.name".into()), Member("x".into())]
+        );
+        assert_eq!(
+            segments("$.contacts[*].phone[2]"),
+            [
+                Member("contacts".into()),
+                AnyIndex,
+                Member("phone".into()),
+                Index(2)
+            ]
+        );
+        assert_eq!(segments("$.*[ 1 ]"), [AnyMember, Index(1)]);
+        assert_eq!(segments("$**.ssn"), [AnyDepth, Member("ssn".into())]);
+    }
+
+    #[test]
+    fn rejects_invalid_paths() {
+        for p in [
+            "", "contact", "$**", "$.a**", "$.", "$..a", "$[x]", "$[1", "$.\"a", "$a",
+        ] {
+            assert!(path(p).is_err(), "{p}");
+        }
+    }
+
+    #[test]
+    fn transforms_matched_leaves_only() {
+        assert_eq!(
+            apply(
+                "$.contacts[*].phone",
+                r#"{"contacts":[{"phone":"1"},{"phone":"2"},{"fax":"3"}],"phone":"4"}"#,
+                replace("x"),
+            ),
+            r#"{"contacts":[{"phone":"x"},{"phone":"x"},{"fax":"3"}],"phone":"4"}"#
+        );
+        assert_eq!(
+            apply(
+                "$**.ssn",
+                r#"{"ssn":"1","a":{"ssn":"2","b":[{"ssn":"3"}]},"c":{"ssn":{"n":"4"}}}"#,
+                replace("x"),
+            ),
+            r#"{"ssn":"x","a":{"ssn":"x","b":[{"ssn":"x"}]},"c":{"ssn":{"n":"4"}}}"#
+        );
+    }
+
+    #[test]
+    fn keeps_the_rest_of_the_document() {
+        assert_eq!(
+            apply(
+                "$.name",
+                r#"{"name":"a","amount":12345678901234.5678,"big":18446744073709551615,"z":1e400,"b":true}"#,
+                replace("x"),
+            ),
+            r#"{"name":"x","amount":12345678901234.5678,"big":18446744073709551615,"z":1e400,"b":true}"#
+        );
+    }
+
+    #[test]
+    fn keeps_json_types_where_possible() {
+        let doc = r#"{"n":1,"b":true,"s":"1","z":null}"#;
+
+        assert_eq!(
+            apply("$.*", doc, replace("2")),
+            r#"{"n":2,"b":2,"s":"2","z":"2"}"#
+        );
+        assert_eq!(
+            apply("$.*", doc, replace("x")),
+            r#"{"n":"x","b":"x","s":"x","z":"x"}"#
+        );
+    }
+
+    #[test]
+    fn leaves_values_which_are_not_json() {
+        assert_eq!(apply("$.a", "not json", replace("x")), "not json");
+    }
+
+    #[test]
+    fn stops_at_the_first_error() {
+        let mut value = Value::Bytes(br#"{"a":"1","b":"2"}"#.to_vec());
+        let mut calls = 0;
+        let result = path("$.*").unwrap().apply(&mut value, |_| {
+            calls += 1;
+            Err(eyre!("no"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+        assert_eq!(value, Value::Bytes(br#"{"a":"1","b":"2"}"#.to_vec()));
+    }
+}
@@ -0,0 +1,228 @@
+//! Fake data for locales the fake crate has little or no data for, through its `Data` trait. Only
+//! the data transforms use is given; the rest falls back to the crate's English data.
+
+use fake::locales::{Data, FR_FR};
+
+/// French names, addresses, companies and phone numbers.
+#[derive(Clone, Copy)]
+pub struct FrFr;
+
+/// German names, addresses, companies and phone numbers.
+#[derive(Clone, Copy)]
+pub struct DeDe;
+
+impl Data for FrFr {
+    const NAME_FIRST_NAME: &'static [&'static str] = <FR_FR as Data>::NAME_FIRST_NAME;
+    const NAME_LAST_NAME: &'static [&'static str] = <FR_FR as Data>::NAME_LAST_NAME;
+
+    // The street type comes first, as in "rue Martin".
+    const ADDRESS_STREET_TPL: &'static str = "{StreetSuffix} {StreetName}";
+    const ADDRESS_STREET_SUFFIX: &'static [&'static str] = &[
+        "rue",
+        "avenue",
+        "boulevard",
+        "place",
+        "allée",
+        "impasse",
+        "chemin",
+        "quai",
+    ];
+    const ADDRESS_SECONDARY_ADDR_TYPE: &'static [&'static str] =
+        &["Appartement", "Bâtiment", "Escalier", "Étage"];
+
+    // City names are made from the suffixes alone, so every template is a real city name.
+    const ADDRESS_CITY_TPL: &'static str = "{CitySuffix}";
+    const ADDRESS_CITY_WITH_PREFIX_TPL: &'static str = "{CitySuffix}";
+    const ADDRESS_CITY_SUFFIX: &'static [&'static str] = &[
+        "Paris",
+        "Marseille",
+        "Lyon",
+        "Toulouse",
+        "Nice",
+        "Nantes",
+        "Montpellier",
+        "Strasbourg",
+        "Bordeaux",
+        "Lille",
+        "Rennes",
+        "Reims",
+        "Toulon",
+        "Saint-Étienne",
+        "Le Havre",
+        "Grenoble",
+        "Dijon",
+        "Angers",
+        "Nîmes",
+        "Clermont-Ferrand",
+        "Aix-en-Provence",
+        "Brest",
+        "Limoges",
+        "Tours",
+        "Amiens",
+        "Perpignan",
+        "Metz",
+        "Besançon",
+        "Orléans",
+        "Rouen",
+    ];
+    // Two character department codes, as the state codes of other locales have two letters.
+    const ADDRESS_STATE_ABBR: &'static [&'static str] = &[
+        "06", "13", "21", "2A", "31", "33", "34", "35", "37", "38", "44", "49", "59", "63", "67",
+        "69", "75", "76", "77", "78", "83", "92", "93", "94",
+    ];
+    const ADDRESS_POSTCODE_FORMATS: &'static [&'static str] = &["#####"];
+
+    const COMPANY_SUFFIX: &'static [&'static str] = &["SA", "SARL", "SAS", "EURL"];
+    const COMPANY_NAME_TPLS: &'static [&'static str] = &[
+        "{Name_1} {Suffix}",
+        "{Name_1} et {Name_2}",
+        "{Name_1}, {Name_2} et Associés",
+    ];
+
+    const PHONE_NUMBER_FORMATS: &'static [&'static str] = &[
+        "01 ## ## ## ##",
+        "02 ## ## ## ##",
+        "03 ## ## ## ##",
+        "04 ## ## ## ##",
+        "05 ## ## ## ##",
+        "09 ## ## ## ##",
+        "06 ## ## ## ##",
+        "07 ## ## ## ##",
+        "+33 1 ## ## ## ##",
+        "+33 6 ## ## ## ##",
+    ];
+}
+
+impl Data for DeDe {
+    const NAME_FIRST_NAME: &'static [&'static str] = &[
+        "Anna",
+        "Ben",
+        "Clara",
+        "David",
+        "Emma",
+        "Felix",
+        "Greta",
+        "Hannah",
+        "Jonas",
+        "Julia",
+        "Katharina",
+        "Leon",
+        "Lukas",
+        "Marie",
+        "Maximilian",
+        "Mia",
+        "Niklas",
+        "Paul",
+        "Sophie",
+        "Tim",
+        "Ursula",
+        "Wolfgang",
+        "Jürgen",
+        "Sabine",
+        "Stefan",
+        "Petra",
+        "Thomas",
+        "Monika",
+        "Andreas",
+        "Birgit",
+    ];
+    const NAME_LAST_NAME: &'static [&'static str] = &[
+        "Müller",
+        "Schmidt",
+        "Schneider",
+        "Fischer",
+        "Weber",
+        "Meyer",
+        "Wagner",
+        "Becker",
+        "Schulz",
+        "Hoffmann",
+        "Schäfer",
+        "Koch",
+        "Bauer",
+        "Richter",
+        "Klein",
+        "Wolf",
+        "Schröder",
+        "Neumann",
+        "Schwarz",
+        "Zimmermann",
+        "Braun",
+        "Krüger",
+        "Hofmann",
+        "Hartmann",
+        "Lange",
+        "Schmitt",
+        "Werner",
+        "Krause",
+        "Meier",
+        "Lehmann",
+    ];
+
+    // The street type is joined to the name, as in "Goethestraße".
+    const ADDRESS_STREET_TPL: &'static str = "{StreetName}{StreetSuffix}";
+    const ADDRESS_STREET_SUFFIX: &'static [&'static str] =
+        &["straße", "weg", "allee", "platz", "ring", "gasse", "damm"];
+    const ADDRESS_SECONDARY_ADDR_TYPE: &'static [&'static str] =
+        &["Wohnung", "Etage", "Zimmer", "Haus"];
+
+    // City names are made from the suffixes alone, so every template is a real city name.
+    const ADDRESS_CITY_TPL: &'static str = "{CitySuffix}";
+    const ADDRESS_CITY_WITH_PREFIX_TPL: &'static str = "{CitySuffix}";
+    const ADDRESS_CITY_SUFFIX: &'static [&'static str] = &[
+        "Berlin",
+        "Hamburg",
+        "München",
+        "Köln",
+        "Frankfurt am Main",
+        "Stuttgart",
+        "Düsseldorf",
+        "Leipzig",
+        "Dortmund",
+        "Essen",
+        "Bremen",
+        "Dresden",
+        "Hannover",
+        "Nürnberg",
+        "Duisburg",
+        "Bochum",
+        "Wuppertal",
+        "Bielefeld",
+        "Bonn",
+        "Münster",
+        "Mannheim",
+        "Karlsruhe",
+        "Augsburg",
+        "Wiesbaden",
+        "Mönchengladbach",
+        "Gelsenkirchen",
+        "Aachen",
+        "Braunschweig",
+        "Kiel",
+        "Freiburg im Breisgau",
+    ];
+    const ADDRESS_STATE_ABBR: &'static [&'static str] = &[
+        "BW", "BY", "BE", "BB", "HB", "HH", "HE", "MV", "NI", "NW", "RP", "SL", "SN", "ST", "SH",
+        "TH",
+    ];
+    const ADDRESS_POSTCODE_FORMATS: &'static [&'static str] = &["#####"];
+
+    const COMPANY_SUFFIX: &'static [&'static str] =
+        &["GmbH", "AG", "KG", "OHG", "GmbH & Co. KG", "e.K."];
+    const COMPANY_NAME_TPLS: &'static [&'static str] =
+        &["{Name_1} {Suffix}", "{Name_1} & {Name_2} {Suffix}"];
+
+    const PHONE_NUMBER_FORMATS: &'static [&'static str] = &[
+        "030 #######",
+        "040 #######",
+        "089 #######",
+        "069 #######",
+        "0221 #######",
+        "0711 #######",
+        "0151 ########",
+        "0170 #######",
+        "0176 ########",
+        "+49 30 #######",
+        "+49 151 ########",
+    ];
+}
@@ -1,25 +1,39 @@
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 
 use clap::Parser;
-use color_eyre::eyre::{Result, WrapErr};
+use color_eyre::eyre::{eyre, Result, WrapErr};
 use indexmap::IndexMap;
 use mysql::prelude::*;
 use rand::{rngs::StdRng, SeedableRng};
 use serde::Deserialize;
 use xxhash_rust::xxh3;
 
+mod condition;
+mod dates;
+mod identifiers;
+mod json_path;
+mod locales;
+mod numbers;
 mod output;
+mod schema;
 mod ser_mysql;
 mod table_info;
 mod trace_filter;
 mod transforms;
 
 use output::*;
+use schema::ForeignKey;
 use table_info::*;
 use trace_filter::*;
 use transforms::*;
 
+/// Number of times a chunk query is attempted before giving up on the table.
+const CHUNK_ATTEMPTS: u64 = 3;
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
@@ -37,115 +51,687 @@ struct Args {
     #[clap(arg_enum, short, long, env, default_value = "dir")]
     output: OutputKind,
 
+    #[clap(arg_enum, short, long, env, default_value = "csv")]
+    format: OutputFormat,
+
     #[clap(short, long, env, default_value = "trunk")]
     target_directory: PathBuf,
 
     #[clap(long, env)]
     compress: bool,
+
+    /// Rows buffered by the output writer before being flushed. In sql format this is also the
+    /// number of rows per INSERT statement.
+    #[clap(long, env, default_value = "1000")]
+    batch_size: usize,
+
+    /// Number of tables to process at the same time.
+    #[clap(short, long, env, default_value = "1")]
+    jobs: usize,
+
+    /// Read every table inside a single REPEATABLE READ transaction started WITH CONSISTENT
+    /// SNAPSHOT so the output is consistent to a point in time. Implies --jobs 1.
+    #[clap(long, env)]
+    consistent_snapshot: bool,
+
+    /// How strings stored in a character set other than utf8 are read. transcode has the server
+    /// convert them to utf8mb4, raw writes the bytes as stored.
+    #[clap(arg_enum, long, env, default_value = "transcode")]
+    charset: ser_mysql::Charset,
+
+    /// How trace filters are applied. view creates a view of each trace filter's rows, inline
+    /// joins its query as a derived table and needs only read access.
+    #[clap(arg_enum, long, env, default_value = "view")]
+    trace_filters: TraceFilterStrategy,
+
+    /// Secret key for hashed transforms, such as email_hash, so their output can't be reversed by
+    /// hashing guessed inputs.
+    #[clap(long, env, hide_env_values = true)]
+    hash_key: Option<String>,
+
+    /// File containing the secret key for hashed transforms, instead of --hash-key.
+    #[clap(long, env)]
+    hash_key_file: Option<PathBuf>,
 }
 
 #[derive(Deserialize, Debug)]
-struct Config {
+pub struct Config {
     databases: IndexMap<String, Database>,
     trace_filters: Option<TraceFilterList>,
 }
 
+/// Settings shared by every table in a run.
+pub struct Context<'a> {
+    pub config: &'a Config,
+    pub output: &'a Output,
+    pub consistent_snapshot: bool,
+    pub charset: ser_mysql::Charset,
+    pub hash_key: Option<HashKey>,
+    pub unique_values: UniqueValues,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Database {
     pub tables: IndexMap<String, Table>,
     pub trace_filters: Option<TraceFilterList>,
+    /// Derive `related_only` for unfiltered tables from their foreign keys.
+    pub auto_related_only: Option<bool>,
+    /// Derive `include_referenced` for every table from its foreign keys.
+    pub auto_include_referenced: Option<bool>,
+    /// Locale of fake data for transforms of the database's tables which don't set one.
+    pub locale: Option<Locale>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Table {
     pub order_column: Option<String>,
+    pub chunk_size: Option<usize>,
     pub filter: Option<String>,
     pub transforms: Option<Vec<Transform>>,
     pub related_only: Option<RelatedTable>,
+    /// Tables whose rows referenced by the exported rows of this table are exported too.
+    pub include_referenced: Option<Vec<RelatedTable>>,
+    /// Export at most this many of the selected rows, the first by order_column.
+    pub limit: Option<usize>,
+    /// Export this percentage of the selected rows, chosen by a hash of the primary key.
+    pub sample_percent: Option<f64>,
+    /// Seed mixed into the sampling hash to choose a different sample.
+    pub sample_seed: Option<u64>,
+    /// Locale of fake data for transforms of the table which don't set one.
+    pub locale: Option<Locale>,
+    pub on_invalid_value: Option<ser_mysql::InvalidValuePolicy>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct RelatedTable {
     pub table: String,
-    pub column: String,
-    pub foreign_column: Option<String>,
+    pub column: Columns,
+    pub foreign_column: Option<Columns>,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
     let f = File::open(args.configfile).wrap_err("Could open config file")?;
-    let config: Config = serde_yaml::from_reader(f).wrap_err("Failed to parse config file")?;
+    let mut config: Config = serde_yaml::from_reader(f).wrap_err("Failed to parse config file")?;
+    config.default_locales();
 
-    let output = Output::new(args.output, &args.target_directory, args.compress)?;
+    let output = Output::new(
+        args.output,
+        args.format,
+        &args.target_directory,
+        args.compress,
+        args.batch_size,
+    )?;
     let opts = mysql::Opts::from_url(&args.database_url)?;
 
+    let hash_key = HashKey::new(args.hash_key.as_deref(), args.hash_key_file.as_deref())?;
+    if hash_key.is_none() && config.has_hashed_transforms() {
+        eprintln!("## No hash key given, hashed values can be reversed by hashing guessed inputs");
+    }
+
+    let jobs = match (args.output, args.jobs) {
+        (_, 0) => 1,
+        (OutputKind::Stdout, n) if n > 1 => {
+            eprintln!("## Tables are written one at a time when output is stdout, ignoring --jobs");
+            1
+        }
+        (_, n) if n > 1 && args.consistent_snapshot => {
+            eprintln!("## A consistent snapshot is read on a single connection, ignoring --jobs");
+            1
+        }
+        (_, n) => n,
+    };
+
     let first_db_name = config
         .databases
         .keys()
         .next()
-        .expect("at least one database is required");
+        .expect("at least one database is required")
+        .clone();
 
-    let mut conn =
-        mysql::Conn::new(mysql::OptsBuilder::from_opts(opts.clone()).db_name(Some(first_db_name)))?;
+    // One connection per job plus one for setting up and cleaning up trace filters.
+    let pool = mysql::Pool::new_manual(
+        1,
+        jobs + 1,
+        mysql::OptsBuilder::from_opts(opts)
+            .db_name(Some(&first_db_name))
+            .init(vec![args.charset.session_sql()]),
+    )?;
 
-    let result = run(&mut conn, &config, &first_db_name, &output);
+    if let Some(tf_list) = &mut config.trace_filters {
+        tf_list.validate()?;
+        tf_list.set_strategy(args.trace_filters);
+    }
+
+    for db in config.databases.values_mut() {
+        if let Some(tf_list) = &mut db.trace_filters {
+            tf_list.validate()?;
+            tf_list.set_strategy(args.trace_filters);
+        }
+    }
+
+    derive_from_foreign_keys(pool.get_conn()?.as_mut(), &mut config)?;
 
-    if let Err(_) = result {
+    for (db_name, db) in config.databases.iter() {
+        db.check_relations(db_name)?;
+
+        for (table_name, table) in db.tables.iter() {
+            table.check(db_name, table_name)?;
+        }
+    }
+
+    let ctx = Context {
+        config: &config,
+        output: &output,
+        consistent_snapshot: args.consistent_snapshot,
+        charset: args.charset,
+        hash_key,
+        unique_values: UniqueValues::default(),
+    };
+
+    let result = run(&pool, &ctx, &first_db_name, jobs);
+
+    if result.is_err() {
         eprintln!("## Cleaning up...");
-        cleanup(&mut conn, &config);
+        match pool.get_conn() {
+            Ok(mut conn) => cleanup(conn.as_mut(), &config),
+            Err(e) => eprintln!(
+                "{:?}",
+                color_eyre::Report::new(e).wrap_err("cleanup failed")
+            ),
+        }
     }
 
     result
 }
 
-fn run(
-    conn: &mut mysql::Conn,
-    config: &Config,
-    first_db_name: &str,
-    output: &Output,
-) -> Result<()> {
+impl Config {
+    /// Give transforms without a locale their table's, or else their database's, locale.
+    fn default_locales(&mut self) {
+        for db in self.databases.values_mut() {
+            for table in db.tables.values_mut() {
+                let locale = table.locale.or(db.locale);
+                for transform in table.transforms.iter_mut().flatten() {
+                    transform.locale = transform.locale.or(locale);
+                }
+            }
+        }
+    }
+
+    fn has_hashed_transforms(&self) -> bool {
+        self.databases
+            .values()
+            .flat_map(|db| db.tables.values())
+            .flat_map(|t| t.transforms.iter().flatten())
+            .any(Transform::is_hashed)
+    }
+
+    /// The trace filters applied to tables of the database.
+    fn trace_filters_for(&self, db: &Database) -> TraceFilterList {
+        self.trace_filters
+            .as_ref()
+            .map(|x| x.append(db.trace_filters.as_ref()))
+            .unwrap_or_else(TraceFilterList::new)
+    }
+}
+
+impl Table {
+    /// The column rows are ordered by; `id`, or the first column when there is no `id`, unless
+    /// configured.
+    fn order_column<'a>(&'a self, info: &'a TableInfo) -> &'a str {
+        self.order_column.as_deref().unwrap_or_else(|| {
+            if info.column_names.iter().any(|s| s == "id") {
+                "id"
+            } else {
+                info.column_names
+                    .first()
+                    .map(|s| s.as_str())
+                    .unwrap_or("id")
+            }
+        })
+    }
+
+    fn check(&self, db_name: &str, table_name: &str) -> Result<()> {
+        if let Some(p) = self.sample_percent.filter(|p| !(0.0..=100.0).contains(p)) {
+            return Err(eyre!(
+                "sample_percent of {db_name}.{table_name} must be between 0 and 100, not {p}"
+            ));
+        }
+
+        if self.chunk_size == Some(0) {
+            return Err(eyre!(
+                "chunk_size of {db_name}.{table_name} must be at least 1"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check that the order column and the columns named by transforms, their conditions and key
+    /// columns are in the table, so a misspelt name fails before any row is processed.
+    fn check_columns(&self, info: &TableInfo) -> Result<()> {
+        if let Some(column) = self.order_column.as_deref().filter(|c| !info.has_column(c)) {
+            return Err(eyre!(
+                "order_column `{column}` of {}.{} isn't a column of the table. Columns: {}",
+                info.db_name,
+                info.table_name,
+                info.column_names.join(", ")
+            ));
+        }
+
+        for transform in self.transforms.as_deref().unwrap_or_default() {
+            let columns = std::iter::once(("column", transform.column.as_str()))
+                .chain(
+                    transform
+                        .key_column
+                        .iter()
+                        .map(|c| ("key_column", c.as_str())),
+                )
+                .chain(
+                    transform
+                        .when
+                        .iter()
+                        .flat_map(|w| w.columns())
+                        .map(|c| ("when", c)),
+                );
+
+            for (option, column) in columns {
+                if !info.has_column(column) {
+                    return Err(eyre!(
+                        "Transform of {}.{} column `{}` has {option} `{column}` which isn't a column of the table. Columns: {}",
+                        info.db_name,
+                        info.table_name,
+                        transform.column,
+                        info.column_names.join(", ")
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The tables this table includes referenced rows from.
+    fn referenced_tables(&self) -> &[RelatedTable] {
+        self.include_referenced.as_deref().unwrap_or_default()
+    }
+}
+
+impl RelatedTable {
+    /// The columns of this table referencing the related table, `id` unless configured.
+    fn foreign_column(&self) -> Columns {
+        self.foreign_column
+            .clone()
+            .unwrap_or_else(|| Columns::from("id"))
+    }
+}
+
+impl Database {
+    /// Check that related_only and include_referenced columns line up, that included tables are
+    /// exported and that the tables selecting each other's rows never lead back to themselves.
+    fn check_relations(&self, db_name: &str) -> Result<()> {
+        for (table_name, table) in self.tables.iter() {
+            for (option, related) in table
+                .related_only
+                .iter()
+                .map(|r| ("related_only", r))
+                .chain(
+                    table
+                        .referenced_tables()
+                        .iter()
+                        .map(|r| ("include_referenced", r)),
+                )
+            {
+                let foreign_column = related.foreign_column();
+                if related.column.is_empty() || related.column.len() != foreign_column.len() {
+                    return Err(eyre!(
+                        "{option} of {db_name}.{table_name}; foreign_column ({foreign_column}) does not line up with column ({}) of {}",
+                        related.column,
+                        related.table
+                    ));
+                }
+            }
+
+            if let Some(related) = table
+                .referenced_tables()
+                .iter()
+                .find(|r| !self.tables.contains_key(&r.table))
+            {
+                return Err(eyre!(
+                    "include_referenced of {db_name}.{table_name}; table {} is not exported",
+                    related.table
+                ));
+            }
+        }
+
+        match self.find_cycle() {
+            Some(path) => Err(eyre!(
+                "related_only or include_referenced cycle in database {db_name}; {}",
+                path.join(" -> ")
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The tables whose selected rows are needed to select the rows of a table; its related_only
+    /// table and the tables including rows they reference from it.
+    fn selection_depends_on<'a>(&'a self, table_name: &str) -> Vec<&'a str> {
+        let related_only = self
+            .tables
+            .get(table_name)
+            .and_then(|t| t.related_only.as_ref())
+            .map(|r| r.table.as_str());
+
+        let referenced_by = self.tables.iter().filter_map(|(name, t)| {
+            t.referenced_tables()
+                .iter()
+                .any(|r| r.table == table_name)
+                .then_some(name.as_str())
+        });
+
+        related_only.into_iter().chain(referenced_by).collect()
+    }
+
+    /// A path of tables which depend on each other to select their rows and leads back to where it
+    /// started.
+    fn find_cycle(&self) -> Option<Vec<&str>> {
+        fn visit<'a>(db: &'a Database, path: &mut Vec<&'a str>) -> bool {
+            let current = path[path.len() - 1];
+            for next in db.selection_depends_on(current) {
+                let seen = path.contains(&next);
+                path.push(next);
+                if seen || visit(db, path) {
+                    return true;
+                }
+                path.pop();
+            }
+            false
+        }
+
+        self.tables.keys().find_map(|table_name| {
+            let mut path = vec![table_name.as_str()];
+            visit(self, &mut path).then_some(path)
+        })
+    }
+
+    /// Set `related_only` on tables which are not otherwise filtered to a table their foreign keys
+    /// reference which is. Tables filtered this way may in turn filter the tables referencing
+    /// them, so this repeats until no more tables are filtered.
+    fn relate_by_foreign_keys(
+        &mut self,
+        db_name: &str,
+        foreign_keys: &[ForeignKey],
+        columns: &HashMap<String, Vec<String>>,
+        trace_filters: &TraceFilterList,
+    ) {
+        let no_columns = Vec::new();
+        let is_filtered = |tables: &IndexMap<String, Table>, table_name: &str| {
+            tables.get(table_name).is_some_and(|t| {
+                t.filter.is_some()
+                    || t.limit.is_some()
+                    || t.sample_percent.is_some()
+                    || t.related_only.is_some()
+                    || trace_filters.matches(
+                        db_name,
+                        table_name,
+                        columns.get(table_name).unwrap_or(&no_columns),
+                    )
+            })
+        };
+
+        loop {
+            let derived: Vec<(String, &ForeignKey)> = self
+                .tables
+                .keys()
+                .filter(|table_name| !is_filtered(&self.tables, table_name))
+                .filter_map(|table_name| {
+                    foreign_keys
+                        .iter()
+                        .filter(|fk| fk.table == *table_name && fk.referenced_table != fk.table)
+                        .find(|fk| is_filtered(&self.tables, &fk.referenced_table))
+                        .map(|fk| (table_name.clone(), fk))
+                })
+                .collect();
+
+            if derived.is_empty() {
+                return;
+            }
+
+            for (table_name, fk) in derived {
+                eprintln!(
+                    "## {db_name}.{table_name} related_only {} by foreign key {}",
+                    fk.referenced_table, fk.name
+                );
+
+                self.tables[&table_name].related_only = Some(RelatedTable {
+                    table: fk.referenced_table.clone(),
+                    column: fk.referenced_columns.clone().into(),
+                    foreign_column: Some(fk.columns.clone().into()),
+                });
+            }
+        }
+    }
+
+    /// Add `include_referenced` to each table for the exported tables its foreign keys reference.
+    /// Foreign keys which would make tables depend on each other's rows to be selected, such as a
+    /// table referencing itself or its related_only table, are skipped.
+    fn include_by_foreign_keys(&mut self, db_name: &str, foreign_keys: &[ForeignKey]) {
+        for fk in foreign_keys {
+            if !self.tables.contains_key(&fk.table)
+                || !self.tables.contains_key(&fk.referenced_table)
+            {
+                continue;
+            }
+
+            let related = RelatedTable {
+                table: fk.referenced_table.clone(),
+                column: fk.referenced_columns.clone().into(),
+                foreign_column: Some(fk.columns.clone().into()),
+            };
+
+            let same = |r: &RelatedTable| {
+                r.table == related.table
+                    && r.column == related.column
+                    && r.foreign_column() == related.foreign_column()
+            };
+
+            // Rows related_only to a table only reference rows of it which are exported already.
+            let table = &mut self.tables[&fk.table];
+            if table.related_only.as_ref().is_some_and(same)
+                || table.referenced_tables().iter().any(same)
+            {
+                continue;
+            }
+
+            table
+                .include_referenced
+                .get_or_insert_with(Vec::new)
+                .push(related);
+
+            if self.find_cycle().is_some() {
+                if let Some(v) = self.tables[&fk.table].include_referenced.as_mut() {
+                    v.pop();
+                }
+                eprintln!(
+                    "## {db_name}.{} not including referenced {} by foreign key {}; it would form a cycle",
+                    fk.table, fk.referenced_table, fk.name
+                );
+                continue;
+            }
+
+            eprintln!(
+                "## {db_name}.{} include_referenced {} by foreign key {}",
+                fk.table, fk.referenced_table, fk.name
+            );
+        }
+    }
+}
+
+/// Read foreign keys for databases with `auto_related_only` or `auto_include_referenced` and
+/// derive their tables' `related_only` and `include_referenced`.
+fn derive_from_foreign_keys(conn: &mut mysql::Conn, config: &mut Config) -> Result<()> {
+    let names: Vec<String> = config
+        .databases
+        .iter()
+        .filter(|(_, db)| {
+            db.auto_related_only.unwrap_or(false) || db.auto_include_referenced.unwrap_or(false)
+        })
+        .map(|(db_name, _)| db_name.clone())
+        .collect();
+
+    for db_name in names {
+        let trace_filters = config.trace_filters_for(&config.databases[&db_name]);
+        let foreign_keys = ForeignKey::for_database(conn, &db_name)?;
+        let db = &mut config.databases[&db_name];
+
+        if db.auto_related_only.unwrap_or(false) {
+            let columns = schema::table_columns(conn, &db_name)?;
+            db.relate_by_foreign_keys(&db_name, &foreign_keys, &columns, &trace_filters);
+        }
+
+        if db.auto_include_referenced.unwrap_or(false) {
+            db.include_by_foreign_keys(&db_name, &foreign_keys);
+        }
+    }
+
+    Ok(())
+}
+
+fn run(pool: &mysql::Pool, ctx: &Context, first_db_name: &str, jobs: usize) -> Result<()> {
+    let config = ctx.config;
+    let mut conn = pool.get_conn()?;
+
+    // All trace filters are set up before any table is processed so tables from every database
+    // can be worked on at the same time.
     if let Some(tf_list) = &config.trace_filters {
-        tf_list.setup(conn, first_db_name)?;
+        tf_list.setup(conn.as_mut(), first_db_name)?;
     }
 
     for (db_name, db) in config.databases.iter() {
-        conn.select_db(db_name);
-
         if let Some(tf_list) = &db.trace_filters {
-            tf_list.setup(conn, db_name)?;
+            conn.as_mut().select_db(db_name);
+            tf_list.setup(conn.as_mut(), db_name)?;
         }
+    }
 
-        for (table_name, table) in db.tables.iter() {
-            let tf_list = config
-                .trace_filters
-                .as_ref()
-                .map(|x| x.append(db.trace_filters.as_ref()))
-                .unwrap_or_else(|| TraceFilterList::new());
+    let tables: VecDeque<(&str, &Database, &str, &Table)> = config
+        .databases
+        .iter()
+        .flat_map(|(db_name, db)| {
+            db.tables
+                .iter()
+                .map(move |(table_name, table)| (db_name.as_str(), db, table_name.as_str(), table))
+        })
+        .collect();
 
-            process_table(conn, &output, tf_list, db_name, db, table_name, table)?;
-        }
+    process_tables(pool, ctx, tables, jobs)?;
 
+    for (db_name, db) in config.databases.iter() {
         if let Some(tf_list) = &db.trace_filters {
-            tf_list.cleanup(conn)?;
+            conn.as_mut().select_db(db_name);
+            tf_list.cleanup(conn.as_mut())?;
         }
     }
 
     if let Some(tf_list) = &config.trace_filters {
-        tf_list.cleanup(conn)?;
+        tf_list.cleanup(conn.as_mut())?;
     }
 
     Ok(())
 }
 
+/// Process tables on `jobs` threads, each with its own connection from the pool. The first error
+/// stops the remaining work and is returned.
+fn process_tables(
+    pool: &mysql::Pool,
+    ctx: &Context,
+    tables: VecDeque<(&str, &Database, &str, &Table)>,
+    jobs: usize,
+) -> Result<()> {
+    let run_progress = Mutex::new(Some(ctx.output.run_progress(tables.len())));
+    let queue = Mutex::new(tables);
+    let failed = AtomicBool::new(false);
+
+    std::thread::scope(|s| {
+        let drawer = s.spawn(|| ctx.output.draw_progress());
+
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                s.spawn(|| -> Result<()> {
+                    let mut conn = pool.get_conn()?;
+
+                    if ctx.consistent_snapshot {
+                        // Trace filter views are created before this point; DDL would implicitly
+                        // commit the transaction.
+                        eprintln!("## Starting transaction with consistent snapshot");
+                        conn.query_drop("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")?;
+                        conn.query_drop("START TRANSACTION WITH CONSISTENT SNAPSHOT, READ ONLY")?;
+                    }
+
+                    while !failed.load(Ordering::Relaxed) {
+                        let next = queue.lock().expect("unpoisoned lock").pop_front();
+                        let (db_name, db, table_name, table) = match next {
+                            Some(job) => job,
+                            None => break,
+                        };
+
+                        conn.as_mut().select_db(db_name);
+
+                        let tf_list = ctx.config.trace_filters_for(db);
+
+                        if let Err(e) = process_table(
+                            conn.as_mut(),
+                            ctx,
+                            tf_list,
+                            db_name,
+                            db,
+                            table_name,
+                            table,
+                        ) {
+                            failed.store(true, Ordering::Relaxed);
+                            return Err(e.wrap_err(format!("Failed on {db_name}.{table_name}")));
+                        }
+
+                        if let Some(p) = run_progress.lock().expect("unpoisoned lock").as_mut() {
+                            p.update(1);
+                        }
+                    }
+
+                    if ctx.consistent_snapshot {
+                        conn.query_drop("COMMIT")?;
+                    }
+
+                    Ok(())
+                })
+            })
+            .collect();
+
+        let results: Vec<Result<()>> = workers
+            .into_iter()
+            .map(|w| {
+                w.join()
+                    .unwrap_or_else(|_| Err(eyre!("worker thread panicked")))
+            })
+            .collect();
+
+        // Finishing the run progress lets the drawer return once the table bars are done.
+        run_progress.lock().expect("unpoisoned lock").take();
+        drawer.join().expect("progress thread panicked")?;
+
+        results.into_iter().collect()
+    })
+}
+
 fn process_table(
     conn: &mut mysql::Conn,
-    output: &Output,
+    ctx: &Context,
     trace_filters: TraceFilterList,
     db_name: &str,
     db: &Database,
     table_name: &str,
     table: &Table,
 ) -> Result<()> {
+    let output = ctx.output;
     let writer = output.writer(db_name, table_name)?;
     let info = match TableInfo::get(conn, db_name, table_name)? {
         Some(info) => info,
@@ -155,55 +741,27 @@ fn process_table(
         }
     };
 
-    let mut join_filter = JoinFilter::default();
-
-    join_filter.append(trace_filters.get_join_filter(&info));
+    table.check_columns(&info)?;
 
-    if let Some(related_only) = &table.related_only {
-        // If table has related_only then we want to join to that other table and let its filtering
-        // filter this table. So we'll need to add the join and then add the trace filters that the
-        // _other table_ would have. OR we could select into a temp table the filter data we need
-        // from the other table and join on that. That seems safer/easier but two steps.
-
-        join_filter.add(
-            format!(
-                "LEFT JOIN `{}` ON `{}`.`{}` = `{}`.`{}`",
-                related_only.table,
-                related_only.table,
-                related_only.column,
-                table_name,
-                related_only.foreign_column.as_deref().unwrap_or("id"),
-            ),
-            format!(
-                "`{}`.`{}` IS NOT NULL",
-                related_only.table, related_only.column
-            ),
-        );
-
-        if !trace_filters.is_empty() {
-            let related_info = match TableInfo::get(conn, db_name, &related_only.table)? {
-                Some(info) => info,
-                None => {
-                    eprintln!("## Related table is empty, not writing; {db_name}.{table_name}");
-                    return Ok(());
-                }
-            };
-
-            let related_filter = db
-                .tables
-                .get(&related_only.table)
-                .and_then(|t| t.filter.as_deref())
-                .unwrap_or("1")
-                .to_owned();
+    let order_column = table.order_column(&info);
 
-            let related_jf = trace_filters.get_join_filter(&related_info);
+    // Keyset pagination skips rows tied with the last row of a chunk, and stops at a null.
+    if table.chunk_size.is_some()
+        && !schema::is_unique_not_null(conn, db_name, table_name, order_column)?
+    {
+        return Err(eyre!(
+            "chunk_size of {db_name}.{table_name} needs order_column `{order_column}` to be a unique, not null column, such as the primary key"
+        ));
+    }
 
-            if !related_jf.is_empty() {
-                join_filter.append(related_jf);
-                join_filter.add_filter(related_filter);
+    let join_filter =
+        match table_join_filter(conn, db_name, db, &trace_filters, &info, Some(table))? {
+            Some(jf) => jf,
+            None => {
+                eprintln!("## Related table is empty, not writing; {db_name}.{table_name}");
+                return Ok(());
             }
-        }
-    }
+        };
 
     let from_where_sql = format!(
         "FROM `{}` {} WHERE {}",
@@ -217,59 +775,349 @@ fn process_table(
     dbg!(&sql);
 
     let row_count: usize = conn.query_first(sql)?.unwrap_or(0);
-    let order_column = table.order_column.as_deref().unwrap_or_else(|| {
-        if info.column_names.iter().any(|s| s == "id") {
-            "id"
-        } else {
-            &info
-                .column_names
-                .first()
-                .map(|s| s.as_str())
-                .unwrap_or("id")
-        }
-    });
-
-    let sql = format!(
-        "SELECT `{}`.* {} ORDER BY `{}`.{} ASC",
-        table_name, &from_where_sql, table_name, order_column,
-    );
-
-    dbg!(&sql);
-
-    let rows: Vec<mysql::Row> = conn.query(sql)?;
 
     let mut progress =
         output.progress_writer(format!("{db_name}.{table_name}").as_str(), row_count);
-    let mut wtr = csv::WriterBuilder::new().from_writer(writer);
-    wtr.serialize(&info.column_names)?;
+    let mut wtr = output.row_writer(writer, &info)?;
 
     let mut rng = get_rng_for_table(db_name, table_name);
 
+    let policy = table.on_invalid_value.unwrap_or_default();
+    let order_index = info.column_names.iter().position(|c| c == order_column);
+
     let mut count = 0;
-    for row in rows.into_iter() {
-        //dbg!("{:?}", &row);
-        let mut values = row.unwrap();
-        for transform in table.transforms.as_ref().unwrap_or(&Vec::new()) {
-            let item = values
-                .get_mut(info.get_column_index(transform.column.as_str()))
-                .expect("valid index");
-
-            transform.kind.apply(
-                &mut rng,
-                transform.config.as_ref(),
-                transform.config2.as_ref(),
-                item,
-            );
-        }
+    let mut skipped = 0;
+    let transforms = table.transforms.as_deref().unwrap_or_default();
+    let unique_sets: Vec<Option<String>> = transforms
+        .iter()
+        .map(|t| t.unique_set(db_name, table_name))
+        .collect();
+
+    let mut write_values = |mut values: Vec<mysql::Value>| -> Result<()> {
+        // Conditions and key columns use the values as read, before any transform changes them.
+        let applies: Vec<(bool, Option<mysql::Value>)> = transforms
+            .iter()
+            .map(|t| {
+                (
+                    t.when.as_ref().is_none_or(|w| w.matches(&info, &values)),
+                    t.key_column
+                        .as_ref()
+                        .map(|c| values[info.get_column_index(c)].clone()),
+                )
+            })
+            .collect();
+
+        for (i, (transform, (_, key_value))) in transforms
+            .iter()
+            .zip(applies)
+            .enumerate()
+            .filter(|(_, (_, a))| a.0)
+        {
+            let index = info.get_column_index(transform.column.as_str());
+            let item = values.get_mut(index).expect("valid index");
+
+            let unique = unique_sets[i].as_deref().map(|s| (&ctx.unique_values, s));
 
-        let ser = &ser_mysql::Row::new(&info.column_types, &values);
-        wtr.serialize(ser)?;
+            transform
+                .apply(
+                    &mut rng,
+                    ctx.hash_key.as_ref(),
+                    item,
+                    key_value.as_ref(),
+                    info.column_types[index],
+                    unique,
+                )
+                .wrap_err_with(|| {
+                    format!(
+                        "Failed to transform {db_name}.{table_name} column `{}` of row {}",
+                        transform.column,
+                        count + 1
+                    )
+                })?;
+        }
 
         count += 1;
         progress.update(count);
+
+        match policy.apply(&info, &mut values) {
+            Ok(true) => {}
+            Ok(false) => {
+                skipped += 1;
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(eyre!(
+                    "Invalid value in {db_name}.{table_name} column `{}` of row {count} ({order_column} = {}); {}",
+                    info.column_names[e.index],
+                    order_index.map_or("?".to_owned(), |i| values[i].as_sql(false)),
+                    e.error,
+                ));
+            }
+        }
+
+        let ser = &ser_mysql::Row::new(&info, &values);
+        wtr.write_row(ser)
+            .wrap_err_with(|| format!("Failed to write row {count} of {db_name}.{table_name}"))?;
+
+        Ok(())
+    };
+
+    match table.chunk_size {
+        None => {
+            let sql = format!(
+                "SELECT `{}`.* {} ORDER BY `{}`.{} ASC",
+                table_name, &from_where_sql, table_name, order_column,
+            );
+
+            dbg!(&sql);
+
+            // Rows are streamed from the server as they are read so memory use doesn't depend on
+            // the size of the table.
+            for row in conn.query_iter(sql)? {
+                //dbg!("{:?}", &row);
+                write_values(row?.unwrap())?;
+            }
+        }
+        Some(chunk_size) => {
+            // Keyset pagination; each chunk picks up after the last order_column value seen so
+            // every query is short and independent of the others.
+            let order_index = info.get_column_index(order_column);
+
+            let mut last: Option<mysql::Value> = None;
+
+            loop {
+                let after = match &last {
+                    Some(v) => format!(
+                        " AND `{}`.{} > {}",
+                        table_name,
+                        order_column,
+                        v.as_sql(conn.no_backslash_escape())
+                    ),
+                    None => String::new(),
+                };
+
+                let sql = format!(
+                    "SELECT `{}`.* {}{} ORDER BY `{}`.{} ASC LIMIT {}",
+                    table_name, &from_where_sql, after, table_name, order_column, chunk_size,
+                );
+
+                let rows = query_chunk(conn, ctx, db_name, &sql)?;
+                let fetched = rows.len();
+
+                for row in rows {
+                    let values = row.unwrap();
+                    last = Some(values[order_index].clone());
+                    write_values(values)?;
+                }
+
+                if fetched < chunk_size {
+                    break;
+                }
+            }
+        }
     }
 
-    Ok(())
+    if skipped > 0 {
+        eprintln!("## Skipped {skipped} rows with invalid values; {db_name}.{table_name}");
+    }
+
+    wtr.finish()
+}
+
+/// The joins and filters selecting the exported rows of a table; the rows it selects itself and,
+/// when other tables include the rows they reference from it, subqueries selecting the rows
+/// referenced by their exported rows. Returns `None` when no rows would be selected.
+fn table_join_filter(
+    conn: &mut mysql::Conn,
+    db_name: &str,
+    db: &Database,
+    trace_filters: &TraceFilterList,
+    info: &TableInfo,
+    table: Option<&Table>,
+) -> Result<Option<JoinFilter>> {
+    let table_name = &info.table_name;
+    let selected = selected_join_filter(conn, db_name, db, trace_filters, info, table)?;
+
+    // Every row is exported already.
+    if selected.as_ref().is_some_and(|jf| jf.is_empty()) {
+        return Ok(selected);
+    }
+
+    let mut referenced = Vec::new();
+    for (referencing_name, referencing) in db.tables.iter() {
+        for related in referencing
+            .referenced_tables()
+            .iter()
+            .filter(|r| r.table == *table_name)
+        {
+            let referencing_info = match TableInfo::get(conn, db_name, referencing_name)? {
+                Some(info) => info,
+                None => continue,
+            };
+
+            let referencing_jf = match table_join_filter(
+                conn,
+                db_name,
+                db,
+                trace_filters,
+                &referencing_info,
+                Some(referencing),
+            )? {
+                Some(jf) => jf,
+                None => continue,
+            };
+
+            let referencing_table_name = format!("`{referencing_name}`");
+            referenced.push(format!(
+                "{} IN (SELECT {} FROM {} {} WHERE {})",
+                related.column.operand(&format!("`{table_name}`")),
+                related.foreign_column().qualified(&referencing_table_name),
+                referencing_table_name,
+                referencing_jf.join_string(),
+                referencing_jf.filter_string(),
+            ));
+        }
+    }
+
+    if referenced.is_empty() {
+        return Ok(selected);
+    }
+
+    let mut join_filter = selected.unwrap_or_else(|| JoinFilter::with_filter("0".to_string()));
+    join_filter.or_filter(referenced.join(" OR "));
+
+    Ok(Some(join_filter))
+}
+
+/// The joins and filters selecting the rows a table selects itself; its trace filters, its own
+/// filter and, when it has related_only, a subquery selecting the exported rows of the related
+/// table. Related tables are resolved the same way so chains of related_only of any length are
+/// followed. Returns `None` when a related table is empty, and so no rows would be selected.
+fn selected_join_filter(
+    conn: &mut mysql::Conn,
+    db_name: &str,
+    db: &Database,
+    trace_filters: &TraceFilterList,
+    info: &TableInfo,
+    table: Option<&Table>,
+) -> Result<Option<JoinFilter>> {
+    let table_name = &info.table_name;
+    let mut join_filter = trace_filters.get_join_filter(info);
+
+    let table = match table {
+        Some(table) => table,
+        None => return Ok(Some(join_filter)),
+    };
+
+    if let Some(filter) = &table.filter {
+        join_filter.add_filter(format!("({filter})"));
+    }
+
+    let quoted_table_name = format!("`{table_name}`");
+
+    if let Some(percent) = table.sample_percent {
+        let key = table_key(conn, info, table)?;
+        // The first 32 bits of the MD5 of the seed and key, as a fraction of 2^32, is stable across
+        // runs where RAND() is not.
+        join_filter.add_filter(format!(
+            "CONV(LEFT(MD5(CONCAT_WS(',', {}, {})), 8), 16, 10) < {}",
+            table.sample_seed.unwrap_or(0),
+            key.qualified(&quoted_table_name),
+            (percent / 100.0 * 4294967296.0).round() as u64,
+        ));
+    }
+
+    if let Some(related_only) = &table.related_only {
+        let related_info = match TableInfo::get(conn, db_name, &related_only.table)? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+
+        let related_table = db.tables.get(&related_only.table);
+        let related_jf = match table_join_filter(
+            conn,
+            db_name,
+            db,
+            trace_filters,
+            &related_info,
+            related_table,
+        )? {
+            Some(jf) => jf,
+            None => return Ok(None),
+        };
+
+        // A subquery rather than a join keeps the related table's filters, which may use
+        // unqualified column names, from being ambiguous and can't duplicate rows.
+        let related_table_name = format!("`{}`", related_only.table);
+        join_filter.add_filter(format!(
+            "{} IN (SELECT {} FROM {} {} WHERE {})",
+            related_only
+                .foreign_column()
+                .operand(&format!("`{table_name}`")),
+            related_only.column.qualified(&related_table_name),
+            related_table_name,
+            related_jf.join_string(),
+            related_jf.filter_string(),
+        ));
+    }
+
+    if let Some(limit) = table.limit {
+        // MySQL does not allow LIMIT in an IN subquery, but does in a derived table within one.
+        let key = table_key(conn, info, table)?;
+        join_filter = JoinFilter::with_filter(format!(
+            "{} IN (SELECT * FROM (SELECT {} FROM {} {} WHERE {} ORDER BY {}.`{}` LIMIT {}) AS limited)",
+            key.operand(&quoted_table_name),
+            key.qualified(&quoted_table_name),
+            quoted_table_name,
+            join_filter.join_string(),
+            join_filter.filter_string(),
+            quoted_table_name,
+            table.order_column(info),
+            limit,
+        ));
+    }
+
+    Ok(Some(join_filter))
+}
+
+/// The columns identifying a row of the table; its primary key, or the order column when it has
+/// none.
+fn table_key(conn: &mut mysql::Conn, info: &TableInfo, table: &Table) -> Result<Columns> {
+    let primary_key = schema::primary_key(conn, &info.db_name, &info.table_name)?;
+
+    Ok(match primary_key.is_empty() {
+        true => Columns::from(table.order_column(info)),
+        false => primary_key.into(),
+    })
+}
+
+/// Run the query for a single chunk. Connection level failures reset the connection and retry the
+/// chunk rather than failing the whole table. Retrying is not possible inside a consistent snapshot
+/// since resetting the connection would end the transaction.
+fn query_chunk(
+    conn: &mut mysql::Conn,
+    ctx: &Context,
+    db_name: &str,
+    sql: &str,
+) -> Result<Vec<mysql::Row>> {
+    let retry = !ctx.consistent_snapshot;
+    let mut attempt = 1;
+
+    loop {
+        match conn.query(sql) {
+            Ok(rows) => return Ok(rows),
+            Err(mysql::Error::MySqlError(e)) => return Err(e.into()),
+            Err(e) if retry && attempt < CHUNK_ATTEMPTS => {
+                eprintln!("## Chunk query failed, retrying ({attempt}/{CHUNK_ATTEMPTS}); {e}");
+                std::thread::sleep(std::time::Duration::from_secs(attempt));
+                conn.reset().wrap_err("Failed to reconnect")?;
+                conn.query_drop(ctx.charset.session_sql())?;
+                conn.select_db(db_name);
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
 }
 
 fn get_rng_for_table(db_name: &str, table_name: &str) -> StdRng {
@@ -294,3 +1142,264 @@ fn cleanup(conn: &mut mysql::Conn, config: &Config) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mysql::consts::ColumnType::{MYSQL_TYPE_LONG, MYSQL_TYPE_VAR_STRING};
+
+    fn users() -> TableInfo {
+        TableInfo::with_columns(
+            "app",
+            "users",
+            &[
+                ("id", MYSQL_TYPE_LONG),
+                ("email", MYSQL_TYPE_VAR_STRING),
+                ("is_test", MYSQL_TYPE_LONG),
+            ],
+        )
+    }
+
+    fn table(yaml: &str) -> Table {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn database(yaml: &str) -> Database {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    /// A foreign key from `table.column` to the `id` of `referenced_table`.
+    fn foreign_key(table: &str, column: &str, referenced_table: &str) -> ForeignKey {
+        ForeignKey {
+            name: format!("fk_{table}_{column}"),
+            table: table.to_owned(),
+            columns: vec![column.to_owned()],
+            referenced_table: referenced_table.to_owned(),
+            referenced_columns: vec!["id".to_owned()],
+        }
+    }
+
+    /// The related_only of the table as `table.column = foreign_column`.
+    fn related_only(db: &Database, table_name: &str) -> Option<String> {
+        db.tables[table_name]
+            .related_only
+            .as_ref()
+            .map(|r| format!("{}.{} = {}", r.table, r.column, r.foreign_column()))
+    }
+
+    #[test]
+    fn transform_columns_must_be_in_the_table() {
+        let ok = table(
+            "
+            transforms:
+              - {column: email, kind: email, key_column: id, when: {column: is_test, eq: 0}}
+            ",
+        );
+        assert!(ok.check_columns(&users()).is_ok());
+
+        for yaml in [
+            "order_column: created",
+            "transforms: [{column: emial, kind: email}]",
+            "transforms: [{column: email, kind: email, key_column: user_id}]",
+            "transforms: [{column: email, kind: email, when: {not: {column: is_tset, eq: 0}}}]",
+        ] {
+            let err = table(yaml).check_columns(&users()).unwrap_err();
+            assert!(err.to_string().contains("isn't a column"), "{err}");
+        }
+    }
+
+    #[test]
+    fn chunk_size_must_be_positive() {
+        assert!(table("chunk_size: 1").check("app", "users").is_ok());
+        let err = table("chunk_size: 0").check("app", "users").unwrap_err();
+        assert!(err.to_string().contains("at least 1"), "{err}");
+    }
+
+    #[test]
+    fn related_only_chains_resolve() {
+        let db = database(
+            "
+            tables:
+              blogs: {filter: 'id = 1'}
+              posts: {related_only: {table: blogs, column: id, foreign_column: blog_id}}
+              comments: {related_only: {table: posts, column: id, foreign_column: post_id}}
+            ",
+        );
+
+        assert!(db.check_relations("app").is_ok());
+        assert_eq!(db.find_cycle(), None);
+        assert_eq!(db.selection_depends_on("comments"), ["posts"]);
+        assert_eq!(db.selection_depends_on("posts"), ["blogs"]);
+        assert!(db.selection_depends_on("blogs").is_empty());
+    }
+
+    #[test]
+    fn related_only_cycles_are_rejected() {
+        for (yaml, path) in [
+            (
+                "
+                tables:
+                  posts: {related_only: {table: comments, column: post_id, foreign_column: id}}
+                  comments: {related_only: {table: posts, column: id, foreign_column: post_id}}
+                ",
+                "posts -> comments -> posts",
+            ),
+            (
+                "
+                tables:
+                  categories: {related_only: {table: categories, column: id, foreign_column: parent_id}}
+                ",
+                "categories -> categories",
+            ),
+            (
+                "
+                tables:
+                  posts: {filter: 'id < 10'}
+                  comments:
+                    related_only: {table: posts, column: id, foreign_column: post_id}
+                    include_referenced: [{table: posts, column: id, foreign_column: post_id}]
+                ",
+                "posts -> comments -> posts",
+            ),
+        ] {
+            let err = database(yaml).check_relations("app").unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("related_only or include_referenced cycle in database app; {path}")
+            );
+        }
+    }
+
+    #[test]
+    fn related_columns_must_line_up() {
+        let db = database(
+            "
+            tables:
+              accounts: {}
+              users:
+                related_only: {table: accounts, column: [tenant_id, id], foreign_column: account_id}
+            ",
+        );
+        let err = db.check_relations("app").unwrap_err();
+        assert!(err.to_string().contains("does not line up"), "{err}");
+    }
+
+    #[test]
+    fn foreign_keys_chain_related_only() {
+        let mut db = database(
+            "
+            tables:
+              blogs: {filter: 'id = 1'}
+              posts: {}
+              comments: {}
+              tags: {}
+            ",
+        );
+        let foreign_keys = [
+            foreign_key("comments", "post_id", "posts"),
+            foreign_key("posts", "blog_id", "blogs"),
+            foreign_key("comments", "reply_to_id", "comments"),
+        ];
+
+        db.relate_by_foreign_keys(
+            "app",
+            &foreign_keys,
+            &HashMap::new(),
+            &TraceFilterList::new(),
+        );
+
+        assert_eq!(related_only(&db, "blogs"), None);
+        assert_eq!(related_only(&db, "posts").unwrap(), "blogs.id = blog_id");
+        assert_eq!(related_only(&db, "comments").unwrap(), "posts.id = post_id");
+        assert_eq!(related_only(&db, "tags"), None);
+        assert!(db.check_relations("app").is_ok());
+    }
+
+    #[test]
+    fn foreign_keys_leave_filtered_tables_alone() {
+        let mut db = database(
+            "
+            tables:
+              users: {filter: 'active = 1'}
+              orders: {}
+              sampled: {sample_percent: 10}
+              limited: {limit: 100}
+              related: {related_only: {table: orders, column: id, foreign_column: order_id}}
+              notes: {}
+            ",
+        );
+        let trace_filters: TraceFilterList = serde_yaml::from_str(
+            "
+            - name: users
+              source: {db: app, table: users, column: id, filter: 'active = 1'}
+              match_columns: [user_id]
+            ",
+        )
+        .unwrap();
+        let columns: HashMap<String, Vec<String>> = [
+            ("orders", vec!["id", "user_id"]),
+            ("sampled", vec!["id", "owner_id"]),
+            ("limited", vec!["id", "owner_id"]),
+            ("related", vec!["id", "owner_id", "order_id"]),
+            ("notes", vec!["id", "owner_id"]),
+        ]
+        .into_iter()
+        .map(|(t, c)| (t.to_owned(), c.into_iter().map(String::from).collect()))
+        .collect();
+        let foreign_keys = [
+            foreign_key("orders", "user_id", "users"),
+            foreign_key("sampled", "owner_id", "users"),
+            foreign_key("limited", "owner_id", "users"),
+            foreign_key("related", "owner_id", "users"),
+            foreign_key("notes", "owner_id", "users"),
+        ];
+
+        db.relate_by_foreign_keys("app", &foreign_keys, &columns, &trace_filters);
+
+        // orders is filtered by the trace filter through its user_id.
+        assert_eq!(related_only(&db, "orders"), None);
+        assert_eq!(related_only(&db, "sampled"), None);
+        assert_eq!(related_only(&db, "limited"), None);
+        assert_eq!(
+            related_only(&db, "related").unwrap(),
+            "orders.id = order_id"
+        );
+        assert_eq!(related_only(&db, "notes").unwrap(), "users.id = owner_id");
+    }
+
+    #[test]
+    fn foreign_keys_include_referenced_without_duplicates_or_cycles() {
+        let mut db = database(
+            "
+            tables:
+              users: {filter: 'active = 1'}
+              posts: {filter: 'published = 1'}
+              comments: {related_only: {table: posts, column: id, foreign_column: post_id}}
+            ",
+        );
+        let foreign_keys = [
+            foreign_key("posts", "author_id", "users"),
+            // The same as comments' related_only; its posts are exported already.
+            foreign_key("comments", "post_id", "posts"),
+            // posts would depend on comments, which depends on posts.
+            foreign_key("comments", "pinned_post_id", "posts"),
+            foreign_key("comments", "reply_to_id", "comments"),
+            foreign_key("comments", "author_id", "users"),
+            foreign_key("posts", "category_id", "categories"),
+        ];
+
+        db.include_by_foreign_keys("app", &foreign_keys);
+
+        let included = |table_name: &str| -> Vec<String> {
+            db.tables[table_name]
+                .referenced_tables()
+                .iter()
+                .map(|r| format!("{}.{} = {}", r.table, r.column, r.foreign_column()))
+                .collect()
+        };
+        assert_eq!(included("posts"), ["users.id = author_id"]);
+        assert_eq!(included("comments"), ["users.id = author_id"]);
+        assert!(included("users").is_empty());
+        assert!(db.check_relations("app").is_ok());
+    }
+}
@@ -0,0 +1,511 @@
+use std::cmp::Ordering;
+
+use mysql::consts::ColumnType;
+use mysql::Value;
+
+/// A numeric value, remembering how it was read so it can be written back the same way. Integers
+/// and decimals are kept exactly; only floating point values are computed with floats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Number(Repr);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Repr {
+    Int(i64),
+    UInt(u64),
+    Float(f32),
+    Double(f64),
+    /// Text without an exponent, such as a DECIMAL or integer column, keeping its number of
+    /// digits after the decimal point.
+    Decimal(Decimal),
+    /// Text of a FLOAT column.
+    FloatText(f32),
+    /// Text of a DOUBLE column, or in scientific notation.
+    DoubleText(f64),
+}
+
+/// An exact decimal number; `units` of 10^-`scale`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decimal {
+    units: i128,
+    scale: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Rounding {
+    HalfAwayFromZero,
+    Floor,
+    Ceiling,
+}
+
+impl Number {
+    /// The number of a value read from the database. `None` for values that aren't numbers, and
+    /// for decimals of more digits than can be computed with exactly.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let repr = match value {
+            Value::Int(n) => Repr::Int(*n),
+            Value::UInt(n) => Repr::UInt(*n),
+            Value::Float(n) => Repr::Float(*n),
+            Value::Double(n) => Repr::Double(*n),
+            Value::Bytes(b) => {
+                let s = std::str::from_utf8(b).ok()?;
+                match s.contains(['e', 'E']) {
+                    true => Repr::DoubleText(s.parse::<f64>().ok().filter(|n| n.is_finite())?),
+                    false => Repr::Decimal(s.parse().ok()?),
+                }
+            }
+            _ => return None,
+        };
+
+        Some(Self(repr))
+    }
+
+    /// The number of a value read from a column of `column_type`. The text of FLOAT and DOUBLE
+    /// columns is computed with as a float, rather than at the number of digits it was printed
+    /// with, so noise isn't rounded away.
+    pub fn from_column(value: &Value, column_type: ColumnType) -> Option<Self> {
+        let text = match value {
+            Value::Bytes(b) => std::str::from_utf8(b).ok()?,
+            _ => return Self::from_value(value),
+        };
+
+        let repr = match column_type {
+            ColumnType::MYSQL_TYPE_FLOAT => {
+                Repr::FloatText(text.parse::<f32>().ok().filter(|n| n.is_finite())?)
+            }
+            ColumnType::MYSQL_TYPE_DOUBLE => {
+                Repr::DoubleText(text.parse::<f64>().ok().filter(|n| n.is_finite())?)
+            }
+            _ => return Self::from_value(value),
+        };
+
+        Some(Self(repr))
+    }
+
+    /// The value, approximately for integers and decimals beyond the precision of an f64.
+    pub fn value(&self) -> f64 {
+        match self.0 {
+            Repr::Int(n) => n as f64,
+            Repr::UInt(n) => n as f64,
+            Repr::Float(n) | Repr::FloatText(n) => n as f64,
+            Repr::Double(n) | Repr::DoubleText(n) => n,
+            Repr::Decimal(d) => d.to_f64(),
+        }
+    }
+
+    /// The same kind of number with the value plus noise, keeping the sign of the original; a
+    /// value of the other sign becomes 0. Noise finer than the number's scale is rounded away.
+    pub fn with_noise(self, noise: f64) -> Self {
+        match self.exact() {
+            Some(d) => {
+                let noise = noise * 10f64.powi(d.scale as i32);
+                if !noise.is_finite() || noise.abs() >= 1e37 {
+                    return self;
+                }
+                let units = match d.units.checked_add(noise.round() as i128) {
+                    Some(units) => units,
+                    None => return self,
+                };
+                let units = match (d.units.signum(), units.signum()) {
+                    (0 | 1, -1) | (-1 | 0, 1) => 0,
+                    _ => units,
+                };
+                self.with_exact(Decimal { units, ..d })
+            }
+            None => {
+                let value = self.value() + noise;
+                self.with_float(match self.value() {
+                    o if o >= 0.0 && value < 0.0 => 0.0,
+                    o if o <= 0.0 && value > 0.0 => 0.0,
+                    _ => value,
+                })
+            }
+        }
+    }
+
+    /// The nearest multiple of `size`, halves rounding away from zero, kept to the number's scale.
+    pub fn round_to(self, size: Decimal) -> Self {
+        match self.exact() {
+            Some(d) => {
+                let scale = d.scale.max(size.scale);
+                let rounded = d
+                    .with_scale(scale, Rounding::HalfAwayFromZero)
+                    .zip(size.with_scale(scale, Rounding::HalfAwayFromZero))
+                    .filter(|(_, size)| size.units > 0)
+                    .and_then(|(d, size)| {
+                        div_round(d.units, size.units)
+                            .checked_mul(size.units)
+                            .map(|units| Decimal { units, scale })
+                    });
+                match rounded {
+                    Some(rounded) => self.with_exact(rounded),
+                    None => self,
+                }
+            }
+            None => {
+                let size = size.to_f64();
+                self.with_float((self.value() / size).round() * size)
+            }
+        }
+    }
+
+    /// The number limited to at least `min` and at most `max`. A bound with more digits after the
+    /// decimal point than the number is rounded towards the other bound.
+    pub fn clamp(self, min: Option<Decimal>, max: Option<Decimal>) -> Self {
+        match self.exact() {
+            Some(mut d) => {
+                if let Some(min) = min.filter(|min| d.compare(min) == Ordering::Less) {
+                    d = min.with_scale(d.scale, Rounding::Ceiling).unwrap_or(d);
+                }
+                if let Some(max) = max.filter(|max| d.compare(max) == Ordering::Greater) {
+                    d = max.with_scale(d.scale, Rounding::Floor).unwrap_or(d);
+                }
+                self.with_exact(d)
+            }
+            None => {
+                let mut value = self.value();
+                if let Some(min) = min {
+                    value = value.max(min.to_f64());
+                }
+                if let Some(max) = max {
+                    value = value.min(max.to_f64());
+                }
+                self.with_float(value)
+            }
+        }
+    }
+
+    /// The value as it was read, with integers and decimals kept to their scale and floating point
+    /// text in the fewest digits that read back as the same number.
+    pub fn to_value(self) -> Value {
+        match self.0 {
+            Repr::Int(n) => Value::Int(n),
+            Repr::UInt(n) => Value::UInt(n),
+            Repr::Float(n) => Value::Float(n),
+            Repr::Double(n) => Value::Double(n),
+            Repr::Decimal(d) => Value::Bytes(d.to_string().into_bytes()),
+            Repr::FloatText(n) => Value::Bytes(shortest(n, n.abs() as f64).into_bytes()),
+            Repr::DoubleText(n) => Value::Bytes(shortest(n, n.abs()).into_bytes()),
+        }
+    }
+
+    /// The value of an integer or decimal.
+    fn exact(&self) -> Option<Decimal> {
+        match self.0 {
+            Repr::Int(n) => Some(Decimal::integer(n.into())),
+            Repr::UInt(n) => Some(Decimal::integer(n.into())),
+            Repr::Decimal(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// The same integer or decimal with a new value, unchanged when the value doesn't fit.
+    fn with_exact(self, value: Decimal) -> Self {
+        let integer = value
+            .with_scale(0, Rounding::HalfAwayFromZero)
+            .map(|d| d.units);
+
+        let repr = match self.0 {
+            Repr::Int(_) => integer.and_then(|n| i64::try_from(n).ok()).map(Repr::Int),
+            Repr::UInt(_) => integer.and_then(|n| u64::try_from(n).ok()).map(Repr::UInt),
+            Repr::Decimal(d) => value
+                .with_scale(d.scale, Rounding::HalfAwayFromZero)
+                .map(Repr::Decimal),
+            _ => None,
+        };
+
+        repr.map_or(self, Self)
+    }
+
+    /// The same floating point number with a new value, unchanged when the value isn't finite.
+    fn with_float(self, value: f64) -> Self {
+        if !value.is_finite() {
+            return self;
+        }
+
+        match self.0 {
+            Repr::Float(_) => Self(Repr::Float(value as f32)),
+            Repr::Double(_) => Self(Repr::Double(value)),
+            Repr::FloatText(_) => Self(Repr::FloatText(value as f32)),
+            Repr::DoubleText(_) => Self(Repr::DoubleText(value)),
+            _ => self,
+        }
+    }
+}
+
+impl Decimal {
+    pub fn integer(n: i128) -> Self {
+        Self { units: n, scale: 0 }
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.units > 0
+    }
+
+    fn to_f64(self) -> f64 {
+        // Parsing the text rounds once, where dividing would round twice.
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    /// The same number with `scale` digits after the decimal point, rounded when there are fewer.
+    /// `None` when it doesn't fit.
+    fn with_scale(self, scale: u32, rounding: Rounding) -> Option<Self> {
+        let units = match scale.cmp(&self.scale) {
+            Ordering::Equal => self.units,
+            Ordering::Greater => self
+                .units
+                .checked_mul(10i128.checked_pow(scale - self.scale)?)?,
+            Ordering::Less => {
+                let divisor = 10i128.checked_pow(self.scale - scale)?;
+                let (quotient, remainder) = (self.units / divisor, self.units % divisor);
+                match rounding {
+                    Rounding::HalfAwayFromZero => div_round(self.units, divisor),
+                    Rounding::Floor if remainder < 0 => quotient - 1,
+                    Rounding::Ceiling if remainder > 0 => quotient + 1,
+                    Rounding::Floor | Rounding::Ceiling => quotient,
+                }
+            }
+        };
+
+        Some(Self { units, scale })
+    }
+
+    fn compare(&self, other: &Self) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        let exact = |d: &Self| d.with_scale(scale, Rounding::HalfAwayFromZero);
+
+        match (exact(self), exact(other)) {
+            (Some(a), Some(b)) => a.units.cmp(&b.units),
+            _ => self
+                .to_f64()
+                .partial_cmp(&other.to_f64())
+                .unwrap_or(Ordering::Equal),
+        }
+    }
+}
+
+/// The shortest text of a float, in scientific notation when it's very large or small as MySQL
+/// prints it.
+fn shortest<T: std::fmt::Display + std::fmt::LowerExp>(n: T, abs: f64) -> String {
+    match abs != 0.0 && !(1e-4..1e15).contains(&abs) {
+        true => format!("{n:e}"),
+        false => format!("{n}"),
+    }
+}
+
+/// `n / d` for a positive `d`, halves rounding away from zero.
+fn div_round(n: i128, d: i128) -> i128 {
+    let (quotient, remainder) = (n / d, n % d);
+    match remainder.unsigned_abs() * 2 >= d.unsigned_abs() {
+        true => quotient + n.signum(),
+        false => quotient,
+    }
+}
+
+impl std::str::FromStr for Decimal {
+    type Err = color_eyre::Report;
+
+    /// Digits with an optional sign and decimal point, such as `-12.50`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || color_eyre::eyre::eyre!("invalid decimal {s:?}");
+
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(invalid());
+        }
+
+        let mut units: i128 = 0;
+        for c in integer.chars().chain(fraction.chars()) {
+            let digit = c.to_digit(10).ok_or_else(invalid)?;
+            units = units
+                .checked_mul(10)
+                .and_then(|u| u.checked_add(digit.into()))
+                .ok_or_else(invalid)?;
+        }
+
+        Ok(Self {
+            units: if negative { -units } else { units },
+            scale: fraction.len() as u32,
+        })
+    }
+}
+
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scale = self.scale as usize;
+        let digits = format!("{:0>width$}", self.units.unsigned_abs(), width = scale + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+
+        if self.units < 0 {
+            f.write_str("-")?;
+        }
+        match fraction {
+            "" => f.write_str(integer),
+            _ => write!(f, "{integer}.{fraction}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Bytes(s.into())
+    }
+
+    fn number(s: &str) -> Number {
+        Number::from_value(&text(s)).unwrap()
+    }
+
+    fn decimal(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn decimals_round_trip() {
+        for s in [
+            "0",
+            "12345678901234.5678",
+            "-0.0001",
+            "-99999999999999.9999",
+            "18446744073709551615",
+            "-9223372036854775808",
+            "0.000",
+        ] {
+            assert_eq!(number(s).to_value(), text(s), "{s}");
+        }
+
+        assert_eq!(number("-0.00").to_value(), text("0.00"));
+        assert_eq!(Number::from_value(&text("1e400")), None);
+        assert_eq!(Number::from_value(&text("x")), None);
+        assert_eq!(Number::from_value(&text("-")), None);
+        assert_eq!(Number::from_value(&text(&"9".repeat(39))), None);
+    }
+
+    #[test]
+    fn clamps_exactly() {
+        let clamp = |s: &str, min: Option<&str>, max: Option<&str>| {
+            number(s)
+                .clamp(min.map(decimal), max.map(decimal))
+                .to_value()
+        };
+
+        assert_eq!(
+            clamp("12345678901234.5678", None, None),
+            text("12345678901234.5678")
+        );
+        assert_eq!(
+            clamp("-9999999999999999.99", None, None),
+            text("-9999999999999999.99")
+        );
+        assert_eq!(
+            clamp("18446744073709551615", Some("0"), None),
+            text("18446744073709551615")
+        );
+        assert_eq!(clamp("5.25", Some("0"), Some("5")), text("5.00"));
+        assert_eq!(clamp("-1", Some("0.5"), None), text("1"));
+        assert_eq!(clamp("0.001", Some("0.0015"), None), text("0.002"));
+        assert_eq!(clamp("9.999", None, Some("9.9985")), text("9.998"));
+        assert_eq!(
+            Number::from_value(&Value::UInt(u64::MAX))
+                .unwrap()
+                .clamp(None, Some(decimal("100000000000000000000")))
+                .to_value(),
+            Value::UInt(u64::MAX)
+        );
+        assert_eq!(
+            Number::from_value(&Value::Double(7.5))
+                .unwrap()
+                .clamp(None, Some(decimal("5")))
+                .to_value(),
+            Value::Double(5.0)
+        );
+    }
+
+    #[test]
+    fn rounds_to_multiples_keeping_the_scale() {
+        let round = |s: &str, size: &str| number(s).round_to(decimal(size)).to_value();
+
+        assert_eq!(round("1234.5678", "1000"), text("1000.0000"));
+        assert_eq!(round("-1500", "1000"), text("-2000"));
+        assert_eq!(round("12.26", "0.5"), text("12.50"));
+        assert_eq!(round("12", "0.5"), text("12"));
+        assert_eq!(round("12.3456", "0.001"), text("12.3460"));
+        assert_eq!(
+            round("12345678901234.5678", "0.01"),
+            text("12345678901234.5700")
+        );
+        assert_eq!(
+            Number::from_value(&Value::Int(-1250))
+                .unwrap()
+                .round_to(decimal("100"))
+                .to_value(),
+            Value::Int(-1300)
+        );
+    }
+
+    #[test]
+    fn adds_noise_keeping_the_sign() {
+        assert_eq!(
+            number("12345678901234.5678").with_noise(0.01).to_value(),
+            text("12345678901234.5778")
+        );
+        assert_eq!(number("-0.0001").with_noise(0.5).to_value(), text("0.0000"));
+        assert_eq!(number("0.50").with_noise(-1.0).to_value(), text("0.00"));
+        assert_eq!(number("10").with_noise(-2.6).to_value(), text("7"));
+        assert_eq!(number("1.5e3").with_noise(1.0).to_value(), text("1501"));
+        assert_eq!(number("1.5e20").with_noise(1e19).to_value(), text("1.6e20"));
+        assert_eq!(
+            Number::from_value(&Value::UInt(3))
+                .unwrap()
+                .with_noise(-5.0)
+                .to_value(),
+            Value::UInt(0)
+        );
+        assert_eq!(
+            Number::from_value(&Value::Int(i64::MAX))
+                .unwrap()
+                .with_noise(10.0)
+                .to_value(),
+            Value::Int(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn float_columns_are_computed_as_floats() {
+        let column = |s: &str, column_type| Number::from_column(&text(s), column_type).unwrap();
+
+        let double = column("3", ColumnType::MYSQL_TYPE_DOUBLE);
+        assert_eq!(double.to_value(), text("3"));
+        assert_eq!(double.with_noise(0.0125).to_value(), text("3.0125"));
+        assert_eq!(
+            column("0.1", ColumnType::MYSQL_TYPE_DOUBLE)
+                .with_noise(0.2)
+                .to_value(),
+            text("0.30000000000000004")
+        );
+        assert_eq!(
+            column("51.5", ColumnType::MYSQL_TYPE_FLOAT)
+                .with_noise(0.01)
+                .to_value(),
+            text("51.51")
+        );
+        assert_eq!(
+            column("1e-7", ColumnType::MYSQL_TYPE_DOUBLE)
+                .with_noise(1e-7)
+                .to_value(),
+            text("2e-7")
+        );
+        assert_eq!(
+            column("3", ColumnType::MYSQL_TYPE_NEWDECIMAL)
+                .with_noise(0.0125)
+                .to_value(),
+            text("3")
+        );
+    }
+}
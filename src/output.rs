@@ -6,7 +6,11 @@ use clap::ArgEnum;
 use color_eyre::eyre::{Result, WrapErr};
 use flate2::{write::GzEncoder, Compression};
 use indicatif::ProgressBar as Bar;
-use indicatif::ProgressStyle;
+use indicatif::{MultiProgress, ProgressStyle};
+use itertools::Itertools;
+
+use crate::ser_mysql;
+use crate::TableInfo;
 
 #[derive(Copy, Clone, Debug, PartialEq, ArgEnum)]
 #[clap(rename_all = "lowercase")]
@@ -15,14 +19,30 @@ pub enum OutputKind {
     Stdout,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, ArgEnum)]
+#[clap(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Csv,
+    Sql,
+}
+
 pub struct Output {
     kind: OutputKind,
+    format: OutputFormat,
     dir: PathBuf,
     compress: bool,
+    batch_size: usize,
+    progress: MultiProgress,
 }
 
 impl Output {
-    pub fn new(kind: OutputKind, dir: &Path, compress: bool) -> Result<Self> {
+    pub fn new(
+        kind: OutputKind,
+        format: OutputFormat,
+        dir: &Path,
+        compress: bool,
+        batch_size: usize,
+    ) -> Result<Self> {
         match kind {
             OutputKind::Dir => Self::init_dir(dir)?,
             OutputKind::Stdout => {}
@@ -30,8 +50,11 @@ impl Output {
 
         Ok(Self {
             kind,
+            format,
             dir: dir.to_owned(),
             compress,
+            batch_size: batch_size.max(1),
+            progress: MultiProgress::new(),
         })
     }
 
@@ -46,11 +69,19 @@ impl Output {
     pub fn writer(&self, db_name: &str, table_name: &str) -> Result<Box<dyn Write>> {
         match self.kind {
             OutputKind::Stdout => {
-                println!("--- {}.{}", db_name, table_name);
+                match self.format {
+                    OutputFormat::Csv => println!("--- {}.{}", db_name, table_name),
+                    OutputFormat::Sql => println!("-- {}.{}", db_name, table_name),
+                }
                 Ok(Box::new(std::io::stdout()))
             }
             OutputKind::Dir => {
-                let ext = if self.compress { "csv.gz" } else { "csv" };
+                let ext = match (self.format, self.compress) {
+                    (OutputFormat::Csv, false) => "csv",
+                    (OutputFormat::Csv, true) => "csv.gz",
+                    (OutputFormat::Sql, false) => "sql",
+                    (OutputFormat::Sql, true) => "sql.gz",
+                };
                 let filename = self.dir.join(Path::new(
                     format!("{}.{}.{}", db_name, table_name, ext).as_str(),
                 ));
@@ -70,21 +101,156 @@ impl Output {
         }
     }
 
+    pub fn row_writer(
+        &self,
+        writer: Box<dyn Write>,
+        info: &TableInfo,
+    ) -> Result<Box<dyn RowWriter>> {
+        match self.format {
+            OutputFormat::Csv => Ok(Box::new(CsvRowWriter::new(writer, info, self.batch_size)?)),
+            OutputFormat::Sql => Ok(Box::new(SqlRowWriter::new(writer, info, self.batch_size)?)),
+        }
+    }
+
     pub fn progress_writer(&self, label: &str, total: usize) -> Box<dyn Progress> {
-        self.kind.progress_writer(label, total)
+        self.kind.progress_writer(&self.progress, label, total)
+    }
+
+    /// Progress of the whole run, counted in tables. Table progress bars are grouped beneath it
+    /// and it must be created before `draw_progress` is called.
+    pub fn run_progress(&self, total_tables: usize) -> Box<dyn Progress> {
+        self.kind
+            .progress_writer(&self.progress, "tables", total_tables)
+    }
+
+    /// Draw progress bars until every one of them has finished. Blocks, so it should be run on
+    /// its own thread.
+    pub fn draw_progress(&self) -> Result<()> {
+        match self.kind {
+            OutputKind::Stdout => Ok(()),
+            OutputKind::Dir => Ok(self.progress.join()?),
+        }
+    }
+}
+
+pub trait RowWriter {
+    fn write_row(&mut self, row: &ser_mysql::Row) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+}
+
+struct CsvRowWriter {
+    wtr: csv::Writer<Box<dyn Write>>,
+    batch_size: usize,
+    pending: usize,
+}
+
+impl CsvRowWriter {
+    fn new(writer: Box<dyn Write>, info: &TableInfo, batch_size: usize) -> Result<Self> {
+        let mut wtr = csv::WriterBuilder::new().from_writer(writer);
+        wtr.serialize(&info.column_names)?;
+        Ok(Self {
+            wtr,
+            batch_size,
+            pending: 0,
+        })
+    }
+}
+
+impl RowWriter for CsvRowWriter {
+    fn write_row(&mut self, row: &ser_mysql::Row) -> Result<()> {
+        self.wtr.serialize(row)?;
+        self.pending += 1;
+        if self.pending >= self.batch_size {
+            self.wtr.flush()?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes rows as multi-row `INSERT` statements. Strings are escaped assuming the default
+/// sql_mode, i.e. without NO_BACKSLASH_ESCAPES.
+struct SqlRowWriter {
+    wtr: Box<dyn Write>,
+    insert: String,
+    batch: Vec<String>,
+    batch_size: usize,
+}
+
+impl SqlRowWriter {
+    fn new(mut wtr: Box<dyn Write>, info: &TableInfo, batch_size: usize) -> Result<Self> {
+        // Each table's file loads on its own, into the database it was exported from.
+        writeln!(wtr, "USE `{}`;", info.db_name)?;
+        writeln!(wtr, "SET NAMES utf8mb4;")?;
+        writeln!(wtr, "SET FOREIGN_KEY_CHECKS = 0;")?;
+
+        let insert = format!(
+            "INSERT INTO `{}` ({}) VALUES\n",
+            info.table_name,
+            info.column_names
+                .iter()
+                .map(|c| format!("`{c}`"))
+                .join(", ")
+        );
+
+        Ok(Self {
+            wtr,
+            insert,
+            batch: Vec::with_capacity(batch_size),
+            batch_size,
+        })
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        self.wtr.write_all(self.insert.as_bytes())?;
+        self.wtr.write_all(self.batch.join(",\n").as_bytes())?;
+        self.wtr.write_all(b";\n")?;
+        self.batch.clear();
+
+        Ok(())
+    }
+}
+
+impl RowWriter for SqlRowWriter {
+    fn write_row(&mut self, row: &ser_mysql::Row) -> Result<()> {
+        self.batch.push(row.to_sql());
+        if self.batch.len() >= self.batch_size {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.write_batch()?;
+        self.wtr.flush()?;
+        Ok(())
     }
 }
 
 impl OutputKind {
-    pub fn progress_writer(&self, label: &str, total: usize) -> Box<dyn Progress> {
+    pub fn progress_writer(
+        &self,
+        multi: &MultiProgress,
+        label: &str,
+        total: usize,
+    ) -> Box<dyn Progress> {
         match self {
             OutputKind::Stdout => Box::new(NullProgress {}),
-            OutputKind::Dir => Box::new(FileProgress::new(label, total)),
+            OutputKind::Dir => Box::new(FileProgress::new(multi, label, total)),
         }
     }
 }
 
-pub trait Progress {
+pub trait Progress: Send {
     fn update(&mut self, _count: usize);
 }
 
@@ -93,8 +259,9 @@ struct FileProgress {
 }
 
 impl FileProgress {
-    pub fn new(label: &str, total: usize) -> Self {
-        let bar = Bar::new(total as u64).with_message(std::borrow::Cow::Owned(label.into()));
+    pub fn new(multi: &MultiProgress, label: &str, total: usize) -> Self {
+        let bar =
+            multi.add(Bar::new(total as u64).with_message(std::borrow::Cow::Owned(label.into())));
         bar.set_style(
             ProgressStyle::default_bar()
                 .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
@@ -123,3 +290,58 @@ impl Drop for FileProgress {
         self.bar.finish_at_current_pos();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mysql::consts::ColumnType::{MYSQL_TYPE_LONG, MYSQL_TYPE_VAR_STRING};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A writer whose output can still be read after it is boxed.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sql_selects_the_database_of_the_table() {
+        let info = TableInfo::with_columns(
+            "app",
+            "users",
+            &[("id", MYSQL_TYPE_LONG), ("name", MYSQL_TYPE_VAR_STRING)],
+        );
+        let out = Shared::default();
+
+        let mut wtr = SqlRowWriter::new(Box::new(out.clone()), &info, 2).unwrap();
+        for (id, name) in [(b"1", b"a"), (b"2", b"b"), (b"3", b"c")] {
+            let values = [
+                mysql::Value::Bytes(id.to_vec()),
+                mysql::Value::Bytes(name.to_vec()),
+            ];
+            wtr.write_row(&ser_mysql::Row::new(&info, &values)).unwrap();
+        }
+        wtr.finish().unwrap();
+
+        assert_eq!(
+            String::from_utf8(out.0.take()).unwrap(),
+            "USE `app`;
+SET NAMES utf8mb4;
+SET FOREIGN_KEY_CHECKS = 0;
+INSERT INTO `users` (`id`, `name`) VALUES
+(1, 'a'),
+(2, 'b');
+INSERT INTO `users` (`id`, `name`) VALUES
+(3, 'c');
+"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use color_eyre::eyre::Result;
+use indexmap::IndexMap;
+use mysql::prelude::*;
+
+#[derive(Debug, Clone)]
+pub struct ForeignKey {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub referenced_table: String,
+    pub referenced_columns: Vec<String>,
+}
+
+impl ForeignKey {
+    /// Foreign keys between tables of the database, in order of table and constraint name.
+    pub fn for_database(conn: &mut mysql::Conn, db_name: &str) -> Result<Vec<Self>> {
+        let sql = r"SELECT k.CONSTRAINT_NAME, k.TABLE_NAME, k.COLUMN_NAME,
+                k.REFERENCED_TABLE_NAME, k.REFERENCED_COLUMN_NAME
+            FROM information_schema.KEY_COLUMN_USAGE k
+            JOIN information_schema.REFERENTIAL_CONSTRAINTS r
+                ON r.CONSTRAINT_SCHEMA = k.CONSTRAINT_SCHEMA
+                AND r.CONSTRAINT_NAME = k.CONSTRAINT_NAME
+                AND r.TABLE_NAME = k.TABLE_NAME
+            WHERE k.TABLE_SCHEMA = ? AND k.REFERENCED_TABLE_SCHEMA = k.TABLE_SCHEMA
+            ORDER BY k.TABLE_NAME, k.CONSTRAINT_NAME, k.ORDINAL_POSITION";
+
+        let rows: Vec<(String, String, String, String, String)> = conn.exec(sql, (db_name,))?;
+
+        let mut keys: IndexMap<(String, String), ForeignKey> = IndexMap::new();
+
+        for (name, table, column, referenced_table, referenced_column) in rows {
+            keys.entry((table.clone(), name.clone()))
+                .or_insert_with(|| ForeignKey {
+                    name,
+                    table,
+                    columns: Vec::new(),
+                    referenced_table,
+                    referenced_columns: Vec::new(),
+                })
+                .push(column, referenced_column);
+        }
+
+        Ok(keys.into_values().collect())
+    }
+
+    fn push(&mut self, column: String, referenced_column: String) {
+        self.columns.push(column);
+        self.referenced_columns.push(referenced_column);
+    }
+}
+
+/// Column names of every table in the database, in column order.
+pub fn table_columns(
+    conn: &mut mysql::Conn,
+    db_name: &str,
+) -> Result<HashMap<String, Vec<String>>> {
+    let sql = r"SELECT TABLE_NAME, COLUMN_NAME FROM information_schema.COLUMNS
+        WHERE TABLE_SCHEMA = ? ORDER BY TABLE_NAME, ORDINAL_POSITION";
+
+    let rows: Vec<(String, String)> = conn.exec(sql, (db_name,))?;
+
+    let mut columns: HashMap<String, Vec<String>> = HashMap::new();
+    for (table, column) in rows {
+        columns.entry(table).or_default().push(column);
+    }
+
+    Ok(columns)
+}
+
+/// Columns of the table's primary key, in key order. Empty when the table has none.
+pub fn primary_key(conn: &mut mysql::Conn, db_name: &str, table_name: &str) -> Result<Vec<String>> {
+    let sql = r"SELECT COLUMN_NAME FROM information_schema.KEY_COLUMN_USAGE
+        WHERE TABLE_SCHEMA = ? AND TABLE_NAME = ? AND CONSTRAINT_NAME = 'PRIMARY'
+        ORDER BY ORDINAL_POSITION";
+
+    Ok(conn.exec(sql, (db_name, table_name))?)
+}
+
+/// Whether the column alone is a unique index of the table and can't be null, so ordering by it
+/// gives every row a distinct place.
+pub fn is_unique_not_null(
+    conn: &mut mysql::Conn,
+    db_name: &str,
+    table_name: &str,
+    column_name: &str,
+) -> Result<bool> {
+    let sql = r"SELECT INDEX_NAME FROM information_schema.STATISTICS
+        WHERE TABLE_SCHEMA = ? AND TABLE_NAME = ? AND NON_UNIQUE = 0
+        GROUP BY INDEX_NAME
+        HAVING COUNT(*) = 1 AND MAX(COLUMN_NAME) = ? AND MAX(NULLABLE) = ''";
+
+    let indexes: Vec<String> = conn.exec(sql, (db_name, table_name, column_name))?;
+
+    Ok(!indexes.is_empty())
+}
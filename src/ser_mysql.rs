@@ -1,42 +1,144 @@
+use clap::ArgEnum;
+use itertools::Itertools;
 use mysql::consts::ColumnType;
+use serde::ser::Error;
+use serde::Deserialize;
 use serde::Serialize;
 use serde::Serializer;
 
+use crate::TableInfo;
+
 #[derive(serde::Serialize)]
 pub struct Row<'a>(Vec<Value<'a>>);
 
-pub struct Value<'a>(&'a mysql::Value, ColumnType);
+/// A value along with the table it came from and the index of its column.
+pub struct Value<'a>(&'a mysql::Value, &'a TableInfo, usize);
+
+/// How strings stored in a character set other than utf8 are read.
+#[derive(Copy, Clone, Debug, PartialEq, ArgEnum)]
+#[clap(rename_all = "lowercase")]
+pub enum Charset {
+    /// Have the server convert strings to utf8mb4. Binary strings are passed through as is.
+    Transcode,
+    /// Read strings as stored, in the column's own character set, and write the bytes unchanged.
+    Raw,
+}
+
+impl Charset {
+    /// Statement run on each connection to choose how the server encodes results.
+    pub fn session_sql(&self) -> &'static str {
+        match self {
+            Charset::Transcode => "SET character_set_results = utf8mb4",
+            Charset::Raw => "SET character_set_results = NULL",
+        }
+    }
+}
 
 impl<'a> Row<'a> {
-    pub fn new(column_types: &[ColumnType], values: &'a [mysql::Value]) -> Self {
-        let mut row = Vec::with_capacity(values.len());
-        for i in 0..values.len() {
-            row.push(Value(&values[i], column_types[i]))
+    pub fn new(info: &'a TableInfo, values: &'a [mysql::Value]) -> Self {
+        Self(
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, v)| Value(v, info, i))
+                .collect(),
+        )
+    }
+
+    /// Render the row as a parenthesized list of SQL literals for use in `INSERT ... VALUES`.
+    pub fn to_sql(&self) -> String {
+        format!("({})", self.0.iter().map(Value::to_sql).join(", "))
+    }
+}
+
+impl<'a> Value<'a> {
+    fn column_type(&self) -> ColumnType {
+        self.1.column_types[self.2]
+    }
+
+    pub fn to_sql(&self) -> String {
+        use mysql::consts::ColumnType::*;
+
+        match (self.0, self.column_type()) {
+            (mysql::Value::Bytes(b), _) if b.is_empty() => "''".to_owned(),
+            (
+                mysql::Value::Bytes(b),
+                MYSQL_TYPE_DECIMAL
+                | MYSQL_TYPE_DOUBLE
+                | MYSQL_TYPE_FLOAT
+                | MYSQL_TYPE_INT24
+                | MYSQL_TYPE_LONG
+                | MYSQL_TYPE_LONGLONG
+                | MYSQL_TYPE_NEWDECIMAL
+                | MYSQL_TYPE_SHORT
+                | MYSQL_TYPE_TINY
+                | MYSQL_TYPE_YEAR,
+            ) if is_numeric_literal(b) => String::from_utf8_lossy(b).into_owned(),
+            (
+                mysql::Value::Bytes(b),
+                MYSQL_TYPE_BIT
+                | MYSQL_TYPE_BLOB
+                | MYSQL_TYPE_GEOMETRY
+                | MYSQL_TYPE_LONG_BLOB
+                | MYSQL_TYPE_MEDIUM_BLOB
+                | MYSQL_TYPE_TINY_BLOB,
+            ) => hex_literal(b),
+            // Strings in another character set are written as is; the server stores the bytes of a
+            // hex literal unchanged.
+            (
+                mysql::Value::Bytes(b),
+                MYSQL_TYPE_ENUM
+                | MYSQL_TYPE_SET
+                | MYSQL_TYPE_STRING
+                | MYSQL_TYPE_VARCHAR
+                | MYSQL_TYPE_VAR_STRING,
+            ) if !self.1.is_utf8(self.2) => hex_literal(b),
+            (date @ mysql::Value::Date(..), t) => format!("'{}'", date_text(date, t)),
+            (v, _) => v.as_sql(false),
         }
-        Self(row)
     }
 }
 
+/// A date as MySQL writes it for the column type; only the date of a DATE column and fractional
+/// seconds only when there are some.
+fn date_text(value: &mysql::Value, column_type: ColumnType) -> String {
+    let (year, month, day, hour, minute, second, micros) = match value {
+        mysql::Value::Date(y, mo, d, h, mi, s, us) => (y, mo, d, h, mi, s, us),
+        _ => unreachable!("date_text of a value which is not a date"),
+    };
+
+    match (column_type, micros) {
+        (ColumnType::MYSQL_TYPE_DATE | ColumnType::MYSQL_TYPE_NEWDATE, _) => {
+            format!("{year:04}-{month:02}-{day:02}")
+        }
+        (_, 0) => format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02}"),
+        _ => format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02}.{micros:06}"),
+    }
+}
+
+fn hex_literal(b: &[u8]) -> String {
+    format!("0x{}", b.iter().map(|c| format!("{c:02X}")).join(""))
+}
+
+fn is_numeric_literal(b: &[u8]) -> bool {
+    b.iter().any(|c| c.is_ascii_digit())
+        && b.iter()
+            .all(|c| c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.' | b'e' | b'E'))
+}
+
 impl<'a> Serialize for Value<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
         use mysql::Value::*;
-        match (self.0, self.1) {
+        match (self.0, self.column_type()) {
             (NULL, _) => serializer.serialize_unit(),
             (Int(x), _) => serializer.serialize_i64(*x),
             (UInt(x), _) => serializer.serialize_u64(*x),
             (Float(x), _) => serializer.serialize_f32(*x),
             (Double(x), _) => serializer.serialize_f64(*x),
-            (Date(year, month, day, hour, minute, second, microsecond), _) => serializer
-                .serialize_str(
-                    format!(
-                        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{}",
-                        year, month, day, hour, minute, second, microsecond
-                    )
-                    .as_str(),
-                ),
+            (date @ Date(..), t) => serializer.serialize_str(&date_text(date, t)),
             (Time(is_negative, days, hours, minutes, seconds, microseconds), _) => serializer
                 .serialize_str(
                     format!(
@@ -49,7 +151,10 @@ impl<'a> Serialize for Value<'a> {
                     )
                     .as_str(),
                 ),
-            (Bytes(b), t) => serialize_mysql_bytes(serializer, t, b),
+            (Bytes(b), t) => serialize_mysql_bytes(serializer, t, self.1.is_utf8(self.2), b)
+                .map_err(|e| {
+                    S::Error::custom(format!("column `{}`; {e}", self.1.column_names[self.2]))
+                }),
         }
     }
 }
@@ -57,14 +162,73 @@ impl<'a> Serialize for Value<'a> {
 fn serialize_mysql_bytes<S>(
     serializer: S,
     column_type: ColumnType,
+    utf8: bool,
     bytes: &[u8],
 ) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
+    match decode_mysql_bytes(column_type, utf8, bytes).map_err(S::Error::custom)? {
+        Decoded::Unit => serializer.serialize_unit(),
+        Decoded::Str(s) => serializer.serialize_str(s),
+        Decoded::Bytes(b) => serializer.serialize_bytes(b),
+        Decoded::I32(n) => serializer.serialize_i32(n),
+        Decoded::I64(n) => serializer.serialize_i64(n),
+        Decoded::U64(n) => serializer.serialize_u64(n),
+        Decoded::F64(n) => serializer.serialize_f64(n),
+    }
+}
+
+enum Decoded<'a> {
+    Unit,
+    Str(&'a str),
+    Bytes(&'a [u8]),
+    I32(i32),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+}
+
+#[derive(Debug)]
+pub struct DecodeError(String);
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// `utf8` is false for strings in any other character set, including binary strings, which are
+/// passed through as bytes.
+fn decode_mysql_bytes(
+    column_type: ColumnType,
+    utf8: bool,
+    bytes: &[u8],
+) -> Result<Decoded<'_>, DecodeError> {
     use mysql::consts::ColumnType::*;
 
+    let text = || {
+        std::str::from_utf8(bytes)
+            .map_err(|e| DecodeError(format!("invalid utf8 for {column_type:?}; {e}")))
+    };
+
+    fn number<T: std::str::FromStr>(s: &str, column_type: ColumnType) -> Result<T, DecodeError> {
+        s.parse()
+            .map_err(|_| DecodeError(format!("invalid number for {column_type:?}; {s:?}")))
+    }
+
     match column_type {
+        MYSQL_TYPE_ENUM
+        | MYSQL_TYPE_SET
+        | MYSQL_TYPE_STRING
+        | MYSQL_TYPE_VARCHAR
+        | MYSQL_TYPE_VAR_STRING
+            if !utf8 =>
+        {
+            Ok(Decoded::Bytes(bytes))
+        }
         MYSQL_TYPE_DATE
         | MYSQL_TYPE_DATETIME
         | MYSQL_TYPE_DATETIME2
@@ -79,44 +243,262 @@ where
         | MYSQL_TYPE_TIMESTAMP
         | MYSQL_TYPE_TIMESTAMP2
         | MYSQL_TYPE_VARCHAR
-        | MYSQL_TYPE_VAR_STRING => serializer.serialize_str(
-            std::str::from_utf8(bytes).expect(format!("valid utf8 for {column_type:?}").as_str()),
-        ),
+        | MYSQL_TYPE_VAR_STRING => Ok(Decoded::Str(text()?)),
         MYSQL_TYPE_LONG_BLOB | MYSQL_TYPE_MEDIUM_BLOB | MYSQL_TYPE_TINY_BLOB | MYSQL_TYPE_BLOB => {
-            serializer.serialize_bytes(bytes)
+            Ok(Decoded::Bytes(bytes))
         }
-        MYSQL_TYPE_INT24 => serializer.serialize_i32(
-            std::str::from_utf8(bytes)
-                .expect("valid utf8")
-                .parse()
-                .expect("valid number"),
-        ),
-        MYSQL_TYPE_NULL => serializer.serialize_unit(),
-        MYSQL_TYPE_DECIMAL | MYSQL_TYPE_DOUBLE | MYSQL_TYPE_FLOAT | MYSQL_TYPE_NEWDECIMAL => {
-            serializer.serialize_f64(
-                std::str::from_utf8(bytes)
-                    .expect("valid utf8")
-                    .parse()
-                    .expect("valid decimal"),
-            )
+        MYSQL_TYPE_INT24 => Ok(Decoded::I32(number(text()?, column_type)?)),
+        MYSQL_TYPE_NULL => Ok(Decoded::Unit),
+        // Decimals are passed through as text; parsing into a float would round values with more
+        // significant digits than an f64 can hold.
+        MYSQL_TYPE_DECIMAL | MYSQL_TYPE_NEWDECIMAL => match text()? {
+            s if is_numeric_literal(s.as_bytes()) => Ok(Decoded::Str(s)),
+            s => Err(DecodeError(format!(
+                "invalid decimal for {column_type:?}; {s:?}"
+            ))),
+        },
+        MYSQL_TYPE_DOUBLE | MYSQL_TYPE_FLOAT => Ok(Decoded::F64(number(text()?, column_type)?)),
+        // BIGINT UNSIGNED may not fit in an i64.
+        MYSQL_TYPE_LONG | MYSQL_TYPE_LONGLONG => match text()? {
+            s if s.starts_with('-') => Ok(Decoded::I64(number(s, column_type)?)),
+            s => Ok(Decoded::U64(number(s, column_type)?)),
+        },
+        MYSQL_TYPE_YEAR | MYSQL_TYPE_TINY | MYSQL_TYPE_SHORT => {
+            Ok(Decoded::I32(number(text()?, column_type)?))
         }
-        MYSQL_TYPE_LONG | MYSQL_TYPE_LONGLONG => serializer.serialize_i64(
-            std::str::from_utf8(bytes)
-                .expect("valid utf8")
-                .parse()
-                .expect("valid long"),
-        ),
-        MYSQL_TYPE_YEAR | MYSQL_TYPE_TINY | MYSQL_TYPE_SHORT => serializer.serialize_i32(
-            std::str::from_utf8(bytes)
-                .expect("valid utf8")
-                .parse()
-                .expect("valid short"),
-        ),
         /*
         MYSQL_TYPE_BIT
         MYSQL_TYPE_TYPED_ARRAY
         MYSQL_TYPE_UNKNOWN
                     */
-        _ => serializer.serialize_bytes(bytes),
+        _ => Ok(Decoded::Bytes(bytes)),
+    }
+}
+
+/// What to do with a value that can't be decoded for its column type, such as a latin1 string
+/// that isn't valid utf8.
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidValuePolicy {
+    /// Stop processing with an error.
+    #[default]
+    Fail,
+    /// Leave the row out of the output.
+    SkipRow,
+    /// Replace the value with NULL.
+    NullValue,
+    /// Replace invalid utf8 sequences with U+FFFD. Values which still can't be decoded, such as
+    /// numbers, are replaced with NULL.
+    Lossy,
+}
+
+/// A value that couldn't be decoded, by the index of its column.
+#[derive(Debug)]
+pub struct InvalidValue {
+    pub index: usize,
+    pub error: DecodeError,
+}
+
+impl InvalidValuePolicy {
+    /// Apply the policy to every value of the row that can't be decoded for its column type.
+    /// Returns `Ok(false)` when the row should be skipped.
+    pub fn apply(
+        &self,
+        info: &TableInfo,
+        values: &mut [mysql::Value],
+    ) -> Result<bool, InvalidValue> {
+        for (index, value) in values.iter_mut().enumerate() {
+            let bytes = match value {
+                mysql::Value::Bytes(b) => b,
+                _ => continue,
+            };
+
+            let column_type = info.column_types[index];
+            let utf8 = info.is_utf8(index);
+
+            let error = match decode_mysql_bytes(column_type, utf8, bytes) {
+                Ok(_) => continue,
+                Err(e) => e,
+            };
+
+            match self {
+                InvalidValuePolicy::Fail => return Err(InvalidValue { index, error }),
+                InvalidValuePolicy::SkipRow => return Ok(false),
+                InvalidValuePolicy::NullValue => *value = mysql::Value::NULL,
+                InvalidValuePolicy::Lossy => {
+                    let lossy = String::from_utf8_lossy(bytes).into_owned().into_bytes();
+                    *value = match decode_mysql_bytes(column_type, utf8, &lossy) {
+                        Ok(_) => mysql::Value::Bytes(lossy),
+                        Err(_) => mysql::Value::NULL,
+                    };
+                }
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mysql::consts::ColumnType::{
+        MYSQL_TYPE_BLOB, MYSQL_TYPE_DATE, MYSQL_TYPE_DATETIME, MYSQL_TYPE_NEWDECIMAL,
+        MYSQL_TYPE_VAR_STRING,
+    };
+
+    const DECIMALS: &[&str] = &[
+        "12345678901234.5678",
+        "-0.0001",
+        "-99999999999999.9999",
+        "0.0000",
+        "99999999999999999999999999999999999.999999999999999999999999999999",
+    ];
+
+    fn decimal_table() -> TableInfo {
+        TableInfo::with_columns("db", "prices", &[("amount", MYSQL_TYPE_NEWDECIMAL)])
+    }
+
+    #[test]
+    fn decimals_round_trip_through_csv() {
+        let info = decimal_table();
+
+        for decimal in DECIMALS {
+            let values = [mysql::Value::Bytes(decimal.as_bytes().to_vec())];
+            let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(Vec::new());
+            wtr.serialize(Row::new(&info, &values)).unwrap();
+
+            let csv = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+            assert_eq!(csv.trim_end(), *decimal);
+        }
+    }
+
+    #[test]
+    fn decimals_round_trip_through_sql() {
+        let info = decimal_table();
+
+        for decimal in DECIMALS {
+            let values = [mysql::Value::Bytes(decimal.as_bytes().to_vec())];
+            assert_eq!(Row::new(&info, &values).to_sql(), format!("({decimal})"));
+        }
+    }
+
+    #[test]
+    fn invalid_decimals_are_rejected() {
+        let info = decimal_table();
+        let mut values = [mysql::Value::Bytes(b"12,5".to_vec())];
+
+        let invalid = InvalidValuePolicy::Fail.apply(&info, &mut values);
+        assert_eq!(invalid.unwrap_err().index, 0);
+    }
+
+    #[test]
+    fn values_are_written_as_sql_literals() {
+        let mut info = TableInfo::with_columns(
+            "db",
+            "files",
+            &[
+                ("name", MYSQL_TYPE_VAR_STRING),
+                ("legacy_name", MYSQL_TYPE_VAR_STRING),
+                ("data", MYSQL_TYPE_BLOB),
+                ("day", MYSQL_TYPE_DATE),
+                ("at", MYSQL_TYPE_DATETIME),
+            ],
+        );
+        info.column_charsets[1] = "latin1".to_owned();
+
+        let sql = |values: &[mysql::Value]| Row::new(&info, values).to_sql();
+
+        assert_eq!(
+            sql(&[
+                mysql::Value::Bytes(br"it's C:\tmp".to_vec()),
+                mysql::Value::Bytes(b"caf\xe9".to_vec()),
+                mysql::Value::Bytes(vec![0, 0xff, b'\'']),
+                mysql::Value::Date(2024, 2, 29, 0, 0, 0, 0),
+                mysql::Value::Date(2024, 2, 29, 13, 5, 9, 120),
+            ]),
+            r"('it\'s C:\\tmp', 0x636166E9, 0x00FF27, '2024-02-29', '2024-02-29 13:05:09.000120')"
+        );
+        assert_eq!(
+            sql(&[
+                mysql::Value::NULL,
+                mysql::Value::Bytes(Vec::new()),
+                mysql::Value::NULL,
+                mysql::Value::NULL,
+                mysql::Value::Date(1999, 12, 31, 23, 59, 59, 0),
+            ]),
+            "(NULL, '', NULL, NULL, '1999-12-31 23:59:59')"
+        );
+    }
+
+    #[test]
+    fn invalid_values_follow_the_policy() {
+        let info = TableInfo::with_columns(
+            "db",
+            "customers",
+            &[
+                ("name", MYSQL_TYPE_VAR_STRING),
+                ("balance", MYSQL_TYPE_NEWDECIMAL),
+            ],
+        );
+        // A latin1 "café" in a utf8 column.
+        let row = || {
+            vec![
+                mysql::Value::Bytes(b"caf\xe9".to_vec()),
+                mysql::Value::Bytes(b"1.50".to_vec()),
+            ]
+        };
+        let apply = |policy: InvalidValuePolicy, mut values: Vec<mysql::Value>| {
+            policy.apply(&info, &mut values).map(|keep| (keep, values))
+        };
+
+        assert_eq!(apply(InvalidValuePolicy::Fail, row()).unwrap_err().index, 0);
+        assert!(!apply(InvalidValuePolicy::SkipRow, row()).unwrap().0);
+        assert_eq!(
+            apply(InvalidValuePolicy::NullValue, row()).unwrap(),
+            (
+                true,
+                vec![mysql::Value::NULL, mysql::Value::Bytes(b"1.50".to_vec())]
+            )
+        );
+        assert_eq!(
+            apply(InvalidValuePolicy::Lossy, row()).unwrap(),
+            (
+                true,
+                vec![
+                    mysql::Value::Bytes("caf\u{FFFD}".into()),
+                    mysql::Value::Bytes(b"1.50".to_vec())
+                ]
+            )
+        );
+
+        // A value lossy decoding can't fix becomes NULL.
+        let bad_number = vec![
+            mysql::Value::Bytes(b"cafe".to_vec()),
+            mysql::Value::Bytes(b"1,50".to_vec()),
+        ];
+        assert_eq!(
+            apply(InvalidValuePolicy::Lossy, bad_number).unwrap(),
+            (
+                true,
+                vec![mysql::Value::Bytes(b"cafe".to_vec()), mysql::Value::NULL]
+            )
+        );
+
+        // Valid rows are left alone by every policy.
+        let valid = vec![
+            mysql::Value::Bytes("café".into()),
+            mysql::Value::Bytes(b"1.50".to_vec()),
+        ];
+        for policy in [
+            InvalidValuePolicy::Fail,
+            InvalidValuePolicy::SkipRow,
+            InvalidValuePolicy::NullValue,
+            InvalidValuePolicy::Lossy,
+        ] {
+            assert_eq!(apply(policy, valid.clone()).unwrap(), (true, valid.clone()));
+        }
     }
 }
@@ -2,7 +2,9 @@ use std::collections::HashMap;
 
 use color_eyre::eyre::ContextCompat;
 use color_eyre::eyre::Result;
+use itertools::Itertools;
 use mysql::prelude::*;
+use serde::Deserialize;
 
 pub struct TableInfo {
     pub db_name: String,
@@ -10,6 +12,9 @@ pub struct TableInfo {
     columns_by_name: HashMap<String, usize>,
     pub column_types: Vec<mysql::consts::ColumnType>,
     pub column_names: Vec<String>,
+    /// Character set of each column as sent by the server, "binary" for binary strings and
+    /// non-string types.
+    pub column_charsets: Vec<String>,
 }
 
 impl TableInfo {
@@ -20,6 +25,7 @@ impl TableInfo {
         match conn.query_first(sql)? {
             None => Ok(None),
             Some(row) => Ok(Some(Self {
+                column_charsets: Self::column_charsets(conn, &row)?,
                 db_name: db_name.into(),
                 table_name: table_name.into(),
                 columns_by_name: Self::index_columns(&row),
@@ -33,6 +39,10 @@ impl TableInfo {
         }
     }
 
+    pub fn has_column(&self, column_name: &str) -> bool {
+        self.columns_by_name.contains_key(column_name)
+    }
+
     pub fn get_column_index(&self, column_name: &str) -> usize {
         *self
             .columns_by_name
@@ -49,6 +59,60 @@ impl TableInfo {
             .expect("valid column")
     }
 
+    /// Information about a table with utf8 columns of the given names and types, without asking a
+    /// server.
+    #[cfg(test)]
+    pub fn with_columns(
+        db_name: &str,
+        table_name: &str,
+        columns: &[(&str, mysql::consts::ColumnType)],
+    ) -> Self {
+        Self {
+            db_name: db_name.into(),
+            table_name: table_name.into(),
+            columns_by_name: columns
+                .iter()
+                .enumerate()
+                .map(|(i, (name, _))| (name.to_string(), i))
+                .collect(),
+            column_types: columns.iter().map(|(_, t)| *t).collect(),
+            column_names: columns.iter().map(|(name, _)| name.to_string()).collect(),
+            column_charsets: columns.iter().map(|_| "utf8mb4".to_owned()).collect(),
+        }
+    }
+
+    /// Whether strings in the column are utf8 encoded. ascii is a subset of utf8.
+    pub fn is_utf8(&self, index: usize) -> bool {
+        matches!(
+            self.column_charsets[index].as_str(),
+            "utf8" | "utf8mb3" | "utf8mb4" | "ascii"
+        )
+    }
+
+    fn column_charsets(conn: &mut mysql::Conn, row: &mysql::Row) -> Result<Vec<String>> {
+        let columns = row.columns_ref();
+        let sql = format!(
+            "SELECT ID, CHARACTER_SET_NAME FROM information_schema.COLLATIONS WHERE ID IN ({})",
+            columns
+                .iter()
+                .map(|c| c.character_set())
+                .unique()
+                .join(", ")
+        );
+
+        let names: HashMap<u16, String> = conn.query(sql)?.into_iter().collect();
+
+        Ok(columns
+            .iter()
+            .map(|c| {
+                names
+                    .get(&c.character_set())
+                    .cloned()
+                    .unwrap_or_else(|| "binary".to_owned())
+            })
+            .collect())
+    }
+
     fn index_columns(row: &mysql::Row) -> HashMap<String, usize> {
         let columns = row.columns_ref();
         let mut index = HashMap::new();
@@ -64,3 +128,75 @@ impl TableInfo {
         row.columns_ref().iter().map(|c| c.column_type()).collect()
     }
 }
+
+/// A column, or several columns making up a composite key. Configured as either a single name or
+/// a list of names.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(from = "OneOrMany")]
+pub struct Columns(Vec<String>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<OneOrMany> for Columns {
+    fn from(v: OneOrMany) -> Self {
+        match v {
+            OneOrMany::One(s) => Self(vec![s]),
+            OneOrMany::Many(v) => Self(v),
+        }
+    }
+}
+
+impl From<&str> for Columns {
+    fn from(s: &str) -> Self {
+        Self(vec![s.to_owned()])
+    }
+}
+
+impl From<Vec<String>> for Columns {
+    fn from(v: Vec<String>) -> Self {
+        Self(v)
+    }
+}
+
+impl std::ops::Deref for Columns {
+    type Target = [String];
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Columns {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.join(", "))
+    }
+}
+
+impl Columns {
+    /// Comma separated list of the columns, each qualified by `table` which should already be
+    /// quoted.
+    pub fn qualified(&self, table: &str) -> String {
+        self.0.iter().map(|c| format!("{table}.`{c}`")).join(", ")
+    }
+
+    /// The columns as a single SQL operand; a row constructor when there is more than one.
+    pub fn operand(&self, table: &str) -> String {
+        match self.0.len() {
+            1 => self.qualified(table),
+            _ => format!("({})", self.qualified(table)),
+        }
+    }
+
+    /// Conditions that each column of `self` equals the same column of `other`.
+    pub fn equals(&self, table: &str, other: &Columns, other_table: &str) -> String {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(a, b)| format!("{table}.`{a}` = {other_table}.`{b}`"))
+            .join(" AND ")
+    }
+}
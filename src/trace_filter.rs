@@ -1,26 +1,42 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 
-use color_eyre::eyre::Result;
+use clap::ArgEnum;
+use color_eyre::eyre::{eyre, Result};
 use mysql::prelude::*;
 use serde::Deserialize;
 
+use crate::table_info::Columns;
 use crate::TableInfo;
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct TraceFilter {
     pub name: String,
     pub source: TraceFilterSource,
-    pub match_columns: Vec<String>,
+    pub match_columns: Vec<Columns>,
     #[serde(skip)]
-    initialized: Rc<RefCell<String>>,
+    initialized: Arc<RwLock<String>>,
+    #[serde(skip)]
+    strategy: TraceFilterStrategy,
+}
+
+/// How the rows selected by a trace filter are made available to the tables joined to it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, ArgEnum)]
+#[clap(rename_all = "lowercase")]
+pub enum TraceFilterStrategy {
+    /// Create a view of the selected rows which tables are joined to. Requires CREATE VIEW and
+    /// DROP privileges.
+    #[default]
+    View,
+    /// Join tables to the trace filter's query as a derived table. Works with a read-only account
+    /// at the cost of the query being run for every table.
+    Inline,
 }
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct TraceFilterSource {
     pub db: String,
     pub table: String,
-    pub column: String,
+    pub column: Columns,
     pub filter: String,
 }
 
@@ -37,21 +53,23 @@ impl TraceFilter {
     fn setup(&self, conn: &mut mysql::Conn, current_db_name: &str) -> Result<()> {
         eprintln!("## Setting up trace filter '{}'", self.name);
 
-        let tmp_table_name = self.tmp_table_name();
+        let tmp_table_name = match self.strategy {
+            TraceFilterStrategy::View => {
+                let tmp_table_name = self.tmp_table_name();
+                let sql = format!(
+                    "CREATE OR REPLACE VIEW {} AS ({})",
+                    tmp_table_name,
+                    self.source_sql()
+                );
 
-        let sql = format!(
-            "CREATE OR REPLACE VIEW {} AS (SELECT `{}` FROM `{}`.`{}` WHERE {} ORDER BY `{}` ASC)",
-            tmp_table_name,
-            self.source.column,
-            self.source.db,
-            self.source.table,
-            self.source.filter,
-            self.source.column,
-        );
+                dbg!(&sql);
 
-        dbg!(&sql);
+                conn.query_drop(sql)?;
 
-        conn.query_drop(sql)?;
+                tmp_table_name
+            }
+            TraceFilterStrategy::Inline => format!("({}) AS t", self.source_sql()),
+        };
 
         let count: usize = conn
             .query_first(format!("SELECT COUNT(*) FROM {tmp_table_name}"))?
@@ -59,63 +77,119 @@ impl TraceFilter {
 
         eprintln!("## Found {count} rows");
 
-        self.initialized.replace(current_db_name.to_owned());
+        *self.initialized.write().expect("unpoisoned lock") = current_db_name.to_owned();
 
         Ok(())
     }
 
     fn cleanup(&self, conn: &mut mysql::Conn) -> Result<()> {
+        if self.strategy == TraceFilterStrategy::Inline {
+            self.initialized.write().expect("unpoisoned lock").clear();
+            return Ok(());
+        }
+
         let sql = format!("DROP VIEW {}", self.tmp_table_name(),);
 
         dbg!(&sql);
 
         conn.query_drop(sql)?;
 
-        self.initialized.replace("".to_owned());
+        self.initialized.write().expect("unpoisoned lock").clear();
 
         Ok(())
     }
 
+    /// The query selecting the values of the source columns matching the filter. Tables are joined
+    /// on the source columns, which unlike `id` need not be unique. Distinct values keep the join
+    /// from repeating rows.
+    fn source_sql(&self) -> String {
+        let columns = self
+            .source
+            .column
+            .qualified(&format!("`{}`", self.source.table));
+
+        format!(
+            "SELECT DISTINCT {} FROM `{}`.`{}` WHERE {} ORDER BY {} ASC",
+            columns, self.source.db, self.source.table, self.source.filter, columns,
+        )
+    }
+
     fn tmp_table_name(&self) -> String {
         let prefix = "_customs_tmp";
-        match self.initialized.borrow() {
-            s if s.is_empty() => format!("`{}_{}`", prefix, self.name),
+        match self.initialized.read().expect("unpoisoned lock").as_str() {
+            "" => format!("`{}_{}`", prefix, self.name),
             s => format!("`{}`.`{}_{}`", s, prefix, self.name),
         }
     }
     fn tmp_table_name_alias(&self, join_table: &str) -> String {
         let prefix = "_customs_tmp";
-        match self.initialized.borrow() {
-            s if s.is_empty() => format!("`{}_{}_{}`", prefix, self.name, join_table),
+        match self.initialized.read().expect("unpoisoned lock").as_str() {
+            "" => format!("`{}_{}_{}`", prefix, self.name, join_table),
             s => format!("`{}_{}_{}_{}`", s, prefix, self.name, join_table),
         }
     }
 
     fn get_join_filter(&self, info: &TableInfo) -> JoinFilter {
         let table_name = &info.table_name;
-        let tmp_table = self.tmp_table_name();
-        let tmp_table_alias = self.tmp_table_name_alias(&table_name);
+        let tmp_table = match self.strategy {
+            TraceFilterStrategy::View => self.tmp_table_name(),
+            TraceFilterStrategy::Inline => format!("({})", self.source_sql()),
+        };
+        let tmp_table_alias = self.tmp_table_name_alias(table_name);
 
         match self.match_column(info) {
-            Some(match_column) => JoinFilter::new(
-                format!(
-                    "LEFT JOIN {tmp_table} AS {tmp_table_alias} ON `{table_name}`.`{match_column}` = {tmp_table_alias}.id"
-                ),
-                format!("{tmp_table_alias}.id IS NOT NULL"),
-            ),
+            Some(match_column) => {
+                let on = match_column.equals(
+                    &format!("`{table_name}`"),
+                    &self.source.column,
+                    &tmp_table_alias,
+                );
+                JoinFilter::new(
+                    format!("LEFT JOIN {tmp_table} AS {tmp_table_alias} ON {on}"),
+                    format!("{tmp_table_alias}.`{}` IS NOT NULL", self.source.column[0]),
+                )
+            }
             None => JoinFilter::default(),
         }
     }
 
-    fn match_column(&self, info: &TableInfo) -> Option<String> {
-        if info.db_name == self.source.db && info.table_name == self.source.table {
-            return Some(self.source.column.clone());
+    fn match_column(&self, info: &TableInfo) -> Option<&Columns> {
+        self.match_column_names(&info.db_name, &info.table_name, &info.column_names)
+    }
+
+    /// The first of `match_columns` whose columns are all present in the table.
+    fn match_column_names(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        column_names: &[String],
+    ) -> Option<&Columns> {
+        if db_name == self.source.db && table_name == self.source.table {
+            return Some(&self.source.column);
         }
 
         self.match_columns
             .iter()
-            .find(|c| info.column_names.contains(c))
-            .map(|s| s.to_owned())
+            .find(|c| c.iter().all(|c| column_names.contains(c)))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.source.column.is_empty() {
+            return Err(eyre!("trace filter '{}' has no source column", self.name));
+        }
+
+        match self
+            .match_columns
+            .iter()
+            .find(|c| c.len() != self.source.column.len())
+        {
+            Some(c) => Err(eyre!(
+                "trace filter '{}' match column ({c}) does not line up with source column ({})",
+                self.name,
+                self.source.column
+            )),
+            None => Ok(()),
+        }
     }
 }
 
@@ -143,6 +217,17 @@ impl TraceFilterList {
         self.0.is_empty()
     }
 
+    /// Check that every trace filter's match columns line up with its source columns.
+    pub fn validate(&self) -> Result<()> {
+        self.iter().try_for_each(TraceFilter::validate)
+    }
+
+    pub fn set_strategy(&mut self, strategy: TraceFilterStrategy) {
+        for tf in self.0.iter_mut() {
+            tf.strategy = strategy;
+        }
+    }
+
     pub fn setup(&self, conn: &mut mysql::Conn, current_db_name: &str) -> Result<()> {
         for tf in self.as_ref() {
             tf.setup(conn, current_db_name)?;
@@ -159,6 +244,14 @@ impl TraceFilterList {
         Ok(())
     }
 
+    /// Whether any trace filter would apply to a table with these columns.
+    pub fn matches(&self, db_name: &str, table_name: &str, column_names: &[String]) -> bool {
+        self.iter().any(|tf| {
+            tf.match_column_names(db_name, table_name, column_names)
+                .is_some()
+        })
+    }
+
     pub fn get_join_filter(&self, info: &TableInfo) -> JoinFilter {
         let mut jf = JoinFilter::default();
 
@@ -178,8 +271,15 @@ impl JoinFilter {
         }
     }
 
+    pub fn with_filter(filter: String) -> Self {
+        Self {
+            joins: Vec::new(),
+            filters: vec![filter],
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.joins.is_empty()
+        self.joins.is_empty() && self.filters.is_empty()
     }
 
     pub fn join_string(&self) -> String {
@@ -203,6 +303,11 @@ impl JoinFilter {
         self.filters.push(filter);
     }
 
+    /// Select rows matching `filter` as well as those matching the current filters.
+    pub fn or_filter(&mut self, filter: String) {
+        self.filters = vec![format!("({} OR {})", self.filter_string(), filter)];
+    }
+
     pub fn append(&mut self, jf: JoinFilter) {
         if jf.is_empty() {
             return;
@@ -221,3 +326,190 @@ impl JoinFilter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mysql::consts::ColumnType::{MYSQL_TYPE_LONG, MYSQL_TYPE_VAR_STRING};
+
+    fn trace_filter(yaml: &str) -> TraceFilter {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn join_sql(tf: &TraceFilter, info: &TableInfo) -> (String, String) {
+        let jf = tf.get_join_filter(info);
+        (jf.join_string(), jf.filter_string())
+    }
+
+    #[test]
+    fn joins_on_a_non_id_source_column() {
+        let tf = trace_filter(
+            "
+            name: users
+            source: {db: app, table: users, column: user_uuid, filter: 'active = 1'}
+            match_columns: [user_uuid]
+            ",
+        );
+        let orders = TableInfo::with_columns(
+            "app",
+            "orders",
+            &[
+                ("id", MYSQL_TYPE_LONG),
+                ("user_uuid", MYSQL_TYPE_VAR_STRING),
+            ],
+        );
+
+        assert_eq!(
+            tf.source_sql(),
+            "SELECT DISTINCT `users`.`user_uuid` FROM `app`.`users` WHERE active = 1 ORDER BY `users`.`user_uuid` ASC"
+        );
+        assert_eq!(
+            join_sql(&tf, &orders),
+            (
+                "LEFT JOIN `_customs_tmp_users` AS `_customs_tmp_users_orders` ON `orders`.`user_uuid` = `_customs_tmp_users_orders`.`user_uuid`".to_owned(),
+                "(`_customs_tmp_users_orders`.`user_uuid` IS NOT NULL)".to_owned(),
+            )
+        );
+    }
+
+    #[test]
+    fn source_table_joins_on_its_source_column() {
+        let tf = trace_filter(
+            "
+            name: users
+            source: {db: app, table: users, column: user_uuid, filter: 'active = 1'}
+            match_columns: [owner_uuid]
+            ",
+        );
+        let users = TableInfo::with_columns(
+            "app",
+            "users",
+            &[
+                ("id", MYSQL_TYPE_LONG),
+                ("user_uuid", MYSQL_TYPE_VAR_STRING),
+            ],
+        );
+
+        assert_eq!(
+            join_sql(&tf, &users).0,
+            "LEFT JOIN `_customs_tmp_users` AS `_customs_tmp_users_users` ON `users`.`user_uuid` = `_customs_tmp_users_users`.`user_uuid`"
+        );
+    }
+
+    #[test]
+    fn tables_without_match_columns_are_not_joined() {
+        let tf = trace_filter(
+            "
+            name: users
+            source: {db: app, table: users, column: user_uuid, filter: 'active = 1'}
+            match_columns: [user_uuid]
+            ",
+        );
+        // A table of the same name in another database isn't the source table.
+        let users = TableInfo::with_columns("archive", "users", &[("id", MYSQL_TYPE_LONG)]);
+
+        assert!(tf.get_join_filter(&users).is_empty());
+    }
+
+    #[test]
+    fn joins_a_source_table_in_another_database() {
+        let tf = trace_filter(
+            "
+            name: accounts
+            source: {db: accounts, table: users, column: id, filter: 'region = \"eu\"'}
+            match_columns: [user_id]
+            ",
+        );
+        let orders = TableInfo::with_columns(
+            "shop",
+            "orders",
+            &[("id", MYSQL_TYPE_LONG), ("user_id", MYSQL_TYPE_LONG)],
+        );
+
+        // The view is created in the database being processed when the trace filter is set up.
+        *tf.initialized.write().unwrap() = "shop".to_owned();
+
+        assert_eq!(
+            tf.source_sql(),
+            "SELECT DISTINCT `users`.`id` FROM `accounts`.`users` WHERE region = \"eu\" ORDER BY `users`.`id` ASC"
+        );
+        assert_eq!(
+            join_sql(&tf, &orders),
+            (
+                "LEFT JOIN `shop`.`_customs_tmp_accounts` AS `shop__customs_tmp_accounts_orders` ON `orders`.`user_id` = `shop__customs_tmp_accounts_orders`.`id`".to_owned(),
+                "(`shop__customs_tmp_accounts_orders`.`id` IS NOT NULL)".to_owned(),
+            )
+        );
+    }
+
+    #[test]
+    fn inline_strategy_joins_the_source_query() {
+        let mut list: TraceFilterList = serde_yaml::from_str(
+            "
+            - name: accounts
+              source: {db: accounts, table: users, column: id, filter: 'region = \"eu\"'}
+              match_columns: [user_id]
+            ",
+        )
+        .unwrap();
+        list.set_strategy(TraceFilterStrategy::Inline);
+
+        let orders = TableInfo::with_columns(
+            "shop",
+            "orders",
+            &[("id", MYSQL_TYPE_LONG), ("user_id", MYSQL_TYPE_LONG)],
+        );
+
+        assert_eq!(
+            list.get_join_filter(&orders).join_string(),
+            "LEFT JOIN (SELECT DISTINCT `users`.`id` FROM `accounts`.`users` WHERE region = \"eu\" ORDER BY `users`.`id` ASC) AS `_customs_tmp_accounts_orders` ON `orders`.`user_id` = `_customs_tmp_accounts_orders`.`id`"
+        );
+    }
+
+    #[test]
+    fn joins_on_composite_columns() {
+        let tf = trace_filter(
+            "
+            name: members
+            source: {db: app, table: members, column: [tenant_id, user_id], filter: 'admin = 1'}
+            match_columns:
+              - [org_id, member_id]
+              - [tenant_id, user_id]
+            ",
+        );
+        let posts = TableInfo::with_columns(
+            "app",
+            "posts",
+            &[
+                ("id", MYSQL_TYPE_LONG),
+                ("tenant_id", MYSQL_TYPE_LONG),
+                ("user_id", MYSQL_TYPE_LONG),
+            ],
+        );
+
+        assert_eq!(
+            tf.source_sql(),
+            "SELECT DISTINCT `members`.`tenant_id`, `members`.`user_id` FROM `app`.`members` WHERE admin = 1 ORDER BY `members`.`tenant_id`, `members`.`user_id` ASC"
+        );
+        assert_eq!(
+            join_sql(&tf, &posts),
+            (
+                "LEFT JOIN `_customs_tmp_members` AS `_customs_tmp_members_posts` ON `posts`.`tenant_id` = `_customs_tmp_members_posts`.`tenant_id` AND `posts`.`user_id` = `_customs_tmp_members_posts`.`user_id`".to_owned(),
+                "(`_customs_tmp_members_posts`.`tenant_id` IS NOT NULL)".to_owned(),
+            )
+        );
+    }
+
+    #[test]
+    fn match_columns_must_line_up_with_the_source() {
+        let tf = trace_filter(
+            "
+            name: members
+            source: {db: app, table: members, column: [tenant_id, user_id], filter: 'admin = 1'}
+            match_columns: [user_id]
+            ",
+        );
+
+        assert!(tf.validate().is_err());
+    }
+}
@@ -1,32 +1,432 @@
+use std::collections::hash_map::{Entry, HashMap};
 use std::net::Ipv6Addr;
 use std::ops::Range;
-use std::ops::Sub;
+use std::path::Path;
 use std::str::from_utf8;
 use std::str::FromStr;
+use std::sync::Mutex;
 
+use color_eyre::eyre::{eyre, WrapErr};
 use color_eyre::Result;
-use fake::faker::address::en::*;
-use fake::faker::company::en::CompanyName;
+use fake::faker::address::raw::{
+    CityName, CountryCode, PostCode, SecondaryAddress, StateAbbr, StreetName, StreetSuffix,
+};
+use fake::faker::company::raw::CompanyName;
 use fake::faker::internet::en::{IPv4, IPv6, MACAddress, SafeEmail, Username};
 use fake::faker::lorem::en::Words;
-use fake::faker::name::en::*;
-use fake::faker::phone_number::en::PhoneNumber;
+use fake::faker::name::raw::{FirstName, LastName, Name};
+use fake::faker::phone_number::raw::PhoneNumber;
+use fake::locales::{EN, JA_JP, ZH_CN, ZH_TW};
 use fake::Fake;
+use hmac::{Hmac, Mac};
 use itertools::Itertools;
+use mysql::consts::ColumnType;
 use mysql::Value;
-use rand::{distributions::Alphanumeric, Rng};
-use regex::Regex;
+use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
+use regex::bytes::Regex;
 use serde::Deserialize;
+use sha2::Sha256;
 use xxhash_rust::xxh3;
 
+use crate::condition::Condition;
+use crate::dates::{self, DateTime, DateUnit};
+use crate::identifiers;
+use crate::json_path::JsonPath;
+use crate::locales;
+use crate::numbers::{Decimal, Number};
+
 static ALPHANUM_LOWER: &str = "abcdefghijklmnopqrstuvwxyz0123456789";
 
+/// Values a unique transform generates for a row before giving up.
+const UNIQUE_ATTEMPTS: usize = 100;
+
 #[derive(Deserialize, Debug)]
 pub struct Transform {
     pub column: String,
     pub kind: TransformKind,
     pub config: Option<String>,
     pub config2: Option<String>,
+    /// Generate values from the original value within this named domain, rather than at random,
+    /// so equal values become the same fake wherever a transform uses the domain.
+    pub domain: Option<String>,
+    /// Generate values from the value of this column in the row, rather than the value being
+    /// transformed, so every value of the same entity is transformed the same way.
+    pub key_column: Option<String>,
+    /// Apply the transform only to rows matching the condition.
+    pub when: Option<Condition>,
+    /// Locale of fake names, addresses, phone numbers and companies. Defaults to the table's, then
+    /// the database's locale.
+    pub locale: Option<Locale>,
+    /// Options of the mask transform.
+    pub mask: Option<Mask>,
+    /// Apply the transform to the values at this path in a JSON column rather than the whole
+    /// value.
+    pub path: Option<JsonPath>,
+    /// Generate again when the value was already generated for the column, so the output can be
+    /// loaded into a column with a unique index.
+    pub unique: Option<bool>,
+    /// Keep generated values unique across every transform with this group rather than within the
+    /// column. Implies `unique`.
+    pub unique_group: Option<String>,
+}
+
+/// Locales of the fake data. Internet and lorem ipsum data is always in English, as is data a
+/// locale has none of: addresses in ja_jp, and everything but names in zh_cn and zh_tw.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Locale {
+    #[default]
+    En,
+    FrFr,
+    DeDe,
+    JaJp,
+    ZhCn,
+    ZhTw,
+}
+
+/// Evaluate the expression with the fake locale of a `Locale` bound to the name.
+macro_rules! localized {
+    ($locale:expr, |$l:ident| $e:expr) => {
+        match $locale {
+            Locale::En => {
+                let $l = EN;
+                $e
+            }
+            Locale::FrFr => {
+                let $l = locales::FrFr;
+                $e
+            }
+            Locale::DeDe => {
+                let $l = locales::DeDe;
+                $e
+            }
+            Locale::JaJp => {
+                let $l = JA_JP;
+                $e
+            }
+            Locale::ZhCn => {
+                let $l = ZH_CN;
+                $e
+            }
+            Locale::ZhTw => {
+                let $l = ZH_TW;
+                $e
+            }
+        }
+    };
+}
+
+/// How the mask transform hides a value.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Mask {
+    /// Characters left as they are at the start.
+    pub keep_prefix: usize,
+    /// Characters left as they are at the end.
+    pub keep_suffix: usize,
+    pub mask_char: char,
+    /// Leave characters other than letters and digits, such as the dashes of a card number, in
+    /// place. They don't count towards keep_prefix or keep_suffix.
+    pub keep_separators: bool,
+    /// Replace the hidden characters with this many mask characters rather than one each.
+    pub mask_length: Option<usize>,
+}
+
+impl Default for Mask {
+    fn default() -> Self {
+        Self {
+            keep_prefix: 0,
+            keep_suffix: 4,
+            mask_char: '*',
+            keep_separators: false,
+            mask_length: None,
+        }
+    }
+}
+
+impl Mask {
+    fn apply(&self, value: &mut Value) {
+        let b = match value {
+            Value::Bytes(b) if !b.is_empty() => b,
+            Value::Bytes(_) | Value::NULL => return,
+            _ => {
+                *value = Value::Bytes(Vec::new());
+                return;
+            }
+        };
+
+        // Strings in other character sets are masked by byte rather than by char.
+        *value = Value::Bytes(match from_utf8(b) {
+            Ok(s) => {
+                let chars: Vec<char> = s.chars().collect();
+                self.mask(&chars, |c| c.is_alphanumeric(), self.mask_char)
+                    .into_iter()
+                    .collect::<String>()
+                    .into_bytes()
+            }
+            Err(_) => {
+                let mask_byte = u8::try_from(self.mask_char)
+                    .ok()
+                    .filter(u8::is_ascii)
+                    .unwrap_or(b'*');
+                self.mask(b, u8::is_ascii_alphanumeric, mask_byte)
+            }
+        });
+    }
+
+    fn mask<T: Copy>(&self, units: &[T], is_counted: impl Fn(&T) -> bool, mask: T) -> Vec<T> {
+        let counted = |u: &T| !self.keep_separators || is_counted(u);
+        let total = units.iter().filter(|u| counted(u)).count();
+
+        // Keeping as many characters as there are would reveal the whole value, so all of a value
+        // this short is hidden.
+        let (prefix, suffix) = match self.keep_prefix + self.keep_suffix < total {
+            true => (self.keep_prefix, self.keep_suffix),
+            false => (0, 0),
+        };
+
+        let mut masked = Vec::with_capacity(units.len());
+        let mut position = 0;
+        let mut hidden = 0;
+
+        for unit in units {
+            if !counted(unit) {
+                if self.mask_length.is_none() || hidden == 0 || position >= total - suffix {
+                    masked.push(*unit);
+                }
+                continue;
+            }
+
+            if position < prefix || position >= total - suffix {
+                masked.push(*unit);
+            } else {
+                if self.mask_length.is_none() {
+                    masked.push(mask);
+                } else if hidden == 0 {
+                    masked.extend(std::iter::repeat_n(mask, self.mask_length.unwrap_or(0)));
+                }
+                hidden += 1;
+            }
+            position += 1;
+        }
+
+        masked
+    }
+}
+
+/// Secret mixed into every hash of an original value so hashes can't be reversed by hashing
+/// guesses. Never printed, including by `Debug`.
+pub struct HashKey(Vec<u8>);
+
+impl std::fmt::Debug for HashKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("HashKey(..)")
+    }
+}
+
+impl HashKey {
+    /// The key given directly or read from a file. Surrounding whitespace, such as a trailing
+    /// newline in the file, is not part of the key.
+    pub fn new(key: Option<&str>, key_file: Option<&Path>) -> Result<Option<Self>> {
+        let key = match (key, key_file) {
+            (Some(_), Some(_)) => {
+                return Err(eyre!(
+                    "only one of a hash key or hash key file may be given"
+                ))
+            }
+            (Some(key), None) => key.trim().as_bytes().to_vec(),
+            (None, Some(path)) => std::fs::read(path)
+                .wrap_err_with(|| format!("Could not read hash key file {}", path.display()))?
+                .trim_ascii()
+                .to_vec(),
+            (None, None) => return Ok(None),
+        };
+
+        match key.is_empty() {
+            true => Err(eyre!("hash key is empty")),
+            false => Ok(Some(Self(key))),
+        }
+    }
+}
+
+/// Values generated by unique transforms, by table column or unique group, shared by every table
+/// of the run.
+#[derive(Debug, Default)]
+pub struct UniqueValues(Mutex<HashMap<String, UniqueSet>>);
+
+/// Generated values with the seed of the generator which first generated them.
+type UniqueSet = HashMap<Vec<u8>, Option<u64>>;
+
+impl UniqueValues {
+    /// Record the value in the set, returning whether it's new. A value seen before is still new
+    /// when it came from the same seed, as a domain or key column transform generates the same
+    /// value for the same original. Compared without case, like most collations; NULL is always
+    /// new.
+    fn insert(&self, set: &str, value: &Value, seed: Option<u64>) -> bool {
+        let value = match value {
+            Value::NULL => return true,
+            Value::Bytes(b) => match from_utf8(b) {
+                Ok(s) => s.to_lowercase().into_bytes(),
+                Err(_) => b.clone(),
+            },
+            v => v.as_sql(true).into_bytes(),
+        };
+
+        let mut sets = self.0.lock().expect("unique values lock poisoned");
+        match sets.entry(set.to_owned()).or_default().entry(value) {
+            Entry::Vacant(e) => {
+                e.insert(seed);
+                true
+            }
+            Entry::Occupied(e) => seed.is_some() && *e.get() == seed,
+        }
+    }
+}
+
+/// Hash of an original value; HMAC-SHA256 with the key when there is one, otherwise unkeyed.
+fn hash(key: Option<&HashKey>, b: &[u8]) -> Vec<u8> {
+    match key {
+        Some(HashKey(key)) => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(b);
+            mac.finalize().into_bytes().to_vec()
+        }
+        None => xxh3::xxh3_128(b).to_le_bytes().to_vec(),
+    }
+}
+
+impl Transform {
+    /// Whether the transform's output is derived from a hash of the original value.
+    pub fn is_hashed(&self) -> bool {
+        self.domain.is_some()
+            || self.key_column.is_some()
+            || matches!(
+                self.kind,
+                TransformKind::EmailHash | TransformKind::DomainHash
+            )
+    }
+
+    /// The set of values the transform's values must be unique within, when they must be; its
+    /// group or the column of the table.
+    pub fn unique_set(&self, db_name: &str, table_name: &str) -> Option<String> {
+        match (&self.unique_group, self.unique) {
+            (Some(group), _) => Some(group.clone()),
+            (None, Some(true)) => Some(format!("{db_name}.{table_name}.{}", self.column)),
+            (None, _) => None,
+        }
+    }
+
+    /// Transform the value, or the values at the transform's path within it. `key_value` is the
+    /// value of the transform's key_column in the row. `unique` is the set of values named by
+    /// `unique_set`. Dates are kept within the days a column of `column_type` can hold, and FLOAT
+    /// and DOUBLE text is computed with as a float.
+    pub fn apply(
+        &self,
+        rng: &mut impl Rng,
+        key: Option<&HashKey>,
+        value: &mut Value,
+        key_value: Option<&Value>,
+        column_type: ColumnType,
+        unique: Option<(&UniqueValues, &str)>,
+    ) -> Result<()> {
+        match &self.path {
+            // Values inside a JSON document don't have the column's type.
+            Some(path) => path.apply(value, |v| {
+                self.apply_value(rng, key, v, key_value, None, unique)
+            }),
+            None => self.apply_value(rng, key, value, key_value, Some(column_type), unique),
+        }
+    }
+
+    fn apply_value(
+        &self,
+        rng: &mut impl Rng,
+        key: Option<&HashKey>,
+        value: &mut Value,
+        key_value: Option<&Value>,
+        column_type: Option<ColumnType>,
+        unique: Option<(&UniqueValues, &str)>,
+    ) -> Result<()> {
+        let locale = self.locale.unwrap_or_default();
+
+        let seed = self.seed(key, key_value.unwrap_or(value));
+        let mut keyed_rng = seed.map(StdRng::seed_from_u64);
+
+        let (unique_values, set) = match unique {
+            Some(unique) => unique,
+            None => {
+                self.generate(rng, keyed_rng.as_mut(), key, locale, column_type, value);
+                return Ok(());
+            }
+        };
+
+        // Each attempt draws on from the same generator, so a keyed transform still generates the
+        // same value for the same original.
+        let original = value.clone();
+        for _ in 0..UNIQUE_ATTEMPTS {
+            *value = original.clone();
+            self.generate(rng, keyed_rng.as_mut(), key, locale, column_type, value);
+            if unique_values.insert(set, value, seed) {
+                return Ok(());
+            }
+        }
+
+        Err(eyre!(
+            "no unique value for {set} after {UNIQUE_ATTEMPTS} attempts; the transform may not have enough values"
+        ))
+    }
+
+    fn generate(
+        &self,
+        rng: &mut impl Rng,
+        keyed_rng: Option<&mut StdRng>,
+        key: Option<&HashKey>,
+        locale: Locale,
+        column_type: Option<ColumnType>,
+        value: &mut Value,
+    ) {
+        if let (TransformKind::Mask, Some(mask)) = (&self.kind, &self.mask) {
+            return mask.apply(value);
+        }
+
+        let (config, config2) = (self.config.as_ref(), self.config2.as_ref());
+
+        match keyed_rng {
+            Some(keyed_rng) => {
+                self.kind
+                    .apply(keyed_rng, key, locale, config, config2, column_type, value)
+            }
+            None => self
+                .kind
+                .apply(rng, key, locale, config, config2, column_type, value),
+        }
+
+        let days = column_type.and_then(dates::column_days);
+        if let (true, Some(days)) = (self.kind.is_date(), days) {
+            if let Some(date) = DateTime::from_value(value) {
+                *value = date.within(&days).to_value();
+            }
+        }
+    }
+
+    /// Seed of a generator from the domain and the original value when the transform has a
+    /// domain. The key_column names the domain when there is none.
+    fn seed(&self, key: Option<&HashKey>, value: &Value) -> Option<u64> {
+        let domain = self.domain.as_ref().or(self.key_column.as_ref())?;
+
+        let mut input = domain.as_bytes().to_vec();
+        input.push(0);
+        match value {
+            Value::Bytes(b) => input.extend_from_slice(b),
+            Value::NULL => {}
+            v => input.extend_from_slice(v.as_sql(true).as_bytes()),
+        }
+
+        let seed = hash(key, &input);
+        Some(u64::from_le_bytes(
+            seed[..8].try_into().expect("hash is at least 8 bytes"),
+        ))
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -35,7 +435,12 @@ pub enum TransformKind {
     Addr1,
     Addr2,
     City,
+    Clamp,
     CountryCode,
+    CreditCard,
+    DateRandom,
+    DateShift,
+    DateTruncate,
     DomainHash,
     Email,
     EmailHash,
@@ -43,12 +448,16 @@ pub enum TransformKind {
     Firstname,
     Fullname,
     Hostname,
+    Iban,
     Ipv4,
     Ipv6,
     Ipv6Bin,
     Lastname,
     LoremIpsum,
     MacAddress,
+    Mask,
+    NoiseGaussian,
+    NoisePercent,
     Null,
     Organization,
     Phone,
@@ -58,17 +467,32 @@ pub enum TransformKind {
     RandomInt,
     RandomMoney,
     Replace,
+    Round,
     ReplaceIfNotEmpty,
+    Ssn,
     StateCode,
     Username,
+    Uuid,
 }
 
 impl TransformKind {
+    /// Whether the transform generates dates.
+    fn is_date(&self) -> bool {
+        matches!(
+            self,
+            TransformKind::DateRandom | TransformKind::DateShift | TransformKind::DateTruncate
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn apply(
         &self,
         rng: &mut impl Rng,
+        key: Option<&HashKey>,
+        locale: Locale,
         config: Option<&String>,
         config2: Option<&String>,
+        column_type: Option<ColumnType>,
         value: &mut Value,
     ) {
         match self {
@@ -84,21 +508,21 @@ impl TransformKind {
                 _ => *value = Value::Bytes(Vec::new()),
             },
             TransformKind::Fullname => {
-                let name: String = Name().fake_with_rng(rng);
+                let name: String = localized!(locale, |l| Name(l).fake_with_rng(rng));
                 *value = Value::Bytes(name.into())
             }
             TransformKind::Firstname => {
-                let name: String = FirstName().fake_with_rng(rng);
+                let name: String = localized!(locale, |l| FirstName(l).fake_with_rng(rng));
                 *value = Value::Bytes(name.into())
             }
             TransformKind::Lastname => {
-                let name: String = LastName().fake_with_rng(rng);
+                let name: String = localized!(locale, |l| LastName(l).fake_with_rng(rng));
                 *value = Value::Bytes(name.into())
             }
             TransformKind::EmailHash => {
                 let email = match value {
-                    Value::Bytes(b) => hash_email(b),
-                    _ => hash_email("".as_bytes()),
+                    Value::Bytes(b) => hash_email(key, b),
+                    _ => hash_email(key, "".as_bytes()),
                 };
                 *value = Value::Bytes(email.into())
             }
@@ -111,7 +535,7 @@ impl TransformKind {
             },
             TransformKind::Organization => match value {
                 Value::Bytes(b) if !b.is_empty() => {
-                    let name: String = CompanyName().fake_with_rng(rng);
+                    let name: String = localized!(locale, |l| CompanyName(l).fake_with_rng(rng));
                     *value = Value::Bytes(name.into());
                 }
                 Value::Bytes(_) => {}
@@ -119,12 +543,26 @@ impl TransformKind {
             },
             TransformKind::Addr1 => match value {
                 Value::Bytes(b) if !b.is_empty() => {
-                    let name = format!(
-                        "{} {} {}",
-                        rng.gen::<u8>(),
-                        StreetName().fake_with_rng::<String, _>(rng),
-                        StreetSuffix().fake_with_rng::<&str, _>(rng)
-                    );
+                    // French and German street names already include the street type, and German
+                    // addresses put the number after the street.
+                    let name = match locale {
+                        Locale::FrFr => format!(
+                            "{} {}",
+                            rng.gen_range(1..200),
+                            StreetName(locales::FrFr).fake_with_rng::<String, _>(rng)
+                        ),
+                        Locale::DeDe => format!(
+                            "{} {}",
+                            StreetName(locales::DeDe).fake_with_rng::<String, _>(rng),
+                            rng.gen_range(1..200)
+                        ),
+                        _ => format!(
+                            "{} {} {}",
+                            rng.gen::<u8>(),
+                            localized!(locale, |l| StreetName(l).fake_with_rng::<String, _>(rng)),
+                            localized!(locale, |l| StreetSuffix(l).fake_with_rng::<&str, _>(rng))
+                        ),
+                    };
                     *value = Value::Bytes(name.into());
                 }
                 Value::Bytes(_) => {}
@@ -132,7 +570,8 @@ impl TransformKind {
             },
             TransformKind::Addr2 => match value {
                 Value::Bytes(b) if !b.is_empty() => {
-                    let name: String = SecondaryAddress().fake_with_rng(rng);
+                    let name: String =
+                        localized!(locale, |l| SecondaryAddress(l).fake_with_rng(rng));
                     *value = Value::Bytes(name.into());
                 }
                 Value::Bytes(_) => {}
@@ -140,7 +579,7 @@ impl TransformKind {
             },
             TransformKind::City => match value {
                 Value::Bytes(b) if !b.is_empty() => {
-                    let name: String = CityName().fake_with_rng(rng);
+                    let name: String = localized!(locale, |l| CityName(l).fake_with_rng(rng));
                     *value = Value::Bytes(name.into());
                 }
                 Value::Bytes(_) => {}
@@ -148,26 +587,67 @@ impl TransformKind {
             },
             TransformKind::PostalCode => match value {
                 Value::Bytes(b) if !b.is_empty() => {
-                    let name: String = PostCode().fake_with_rng(rng);
+                    let name: String = localized!(locale, |l| PostCode(l).fake_with_rng(rng));
                     *value = Value::Bytes(name.into());
                 }
                 Value::Bytes(_) => {}
                 _ => *value = Value::Bytes(Vec::new()),
             },
+            TransformKind::Mask => Mask::default().apply(value),
+            // Values which can't be read as text, such as strings in another character set, are
+            // replaced rather than passed through.
+            TransformKind::CreditCard => {
+                if let Some(s) = as_text(value) {
+                    let card = match s {
+                        Ok(s) => identifiers::credit_card(rng, s),
+                        Err(_) => identifiers::random_credit_card(rng),
+                    };
+                    *value = Value::Bytes(card.into_bytes());
+                }
+            }
+            TransformKind::Ssn => {
+                if let Some(s) = as_text(value) {
+                    *value = Value::Bytes(identifiers::ssn(rng, s.unwrap_or("")).into_bytes());
+                }
+            }
+            TransformKind::Iban => {
+                if let Some(s) = as_text(value) {
+                    let iban = match s {
+                        Ok(s) => identifiers::iban(rng, s),
+                        Err(_) => identifiers::random_iban(rng),
+                    };
+                    *value = Value::Bytes(iban.into_bytes());
+                }
+            }
+            TransformKind::Uuid => match value {
+                Value::Bytes(b) if !b.is_empty() => {
+                    *value = Value::Bytes(identifiers::uuid(rng, b))
+                }
+                _ => {}
+            },
             TransformKind::Hostname => match value {
                 Value::Bytes(b) if !b.is_empty() => {
-                    let orig = from_utf8(b).unwrap_or("");
-                    let name = match orig.len() {
-                        0 | 1 | 2 => orig.to_owned(),
-                        len => format!("{}{}", &orig[0..2], random_alphanum_lower(rng, len - 2)),
+                    // Strings in other character sets are kept by byte rather than by char.
+                    let (keep, len) = match from_utf8(b) {
+                        Ok(s) => (
+                            s.chars().take(2).map(char::len_utf8).sum(),
+                            s.chars().count(),
+                        ),
+                        Err(_) => (2, b.len()),
                     };
-                    *value = Value::Bytes(name.into());
+                    if len > 2 {
+                        let mut name = b[0..keep].to_vec();
+                        name.extend(random_alphanum_lower(rng, len - 2).into_bytes());
+                        *value = Value::Bytes(name);
+                    }
                 }
                 Value::Bytes(_) => {}
                 _ => *value = Value::Bytes(Vec::new()),
             },
             TransformKind::DomainHash => match value {
-                Value::Bytes(b) if !b.is_empty() => *value = Value::Bytes(hash_domain(b).into()),
+                Value::Bytes(b) if !b.is_empty() => {
+                    *value = Value::Bytes(hash_domain(key, b).into())
+                }
                 Value::Bytes(_) => {}
                 _ => *value = Value::Bytes(Vec::new()),
             },
@@ -225,21 +705,29 @@ impl TransformKind {
             },
             TransformKind::Phone => match value {
                 Value::Bytes(b) if !b.is_empty() => {
-                    *value = Value::Bytes(PhoneNumber().fake_with_rng::<String, _>(rng).into())
+                    *value = Value::Bytes(
+                        localized!(locale, |l| PhoneNumber(l).fake_with_rng::<String, _>(rng))
+                            .into(),
+                    )
                 }
                 Value::Bytes(_) => {}
                 _ => *value = Value::Bytes(Vec::new()),
             },
             TransformKind::StateCode => match value {
                 Value::Bytes(b) if !b.is_empty() => {
-                    *value = Value::Bytes(StateAbbr().fake_with_rng::<String, _>(rng).into())
+                    *value = Value::Bytes(
+                        localized!(locale, |l| StateAbbr(l).fake_with_rng::<String, _>(rng)).into(),
+                    )
                 }
                 Value::Bytes(_) => {}
                 _ => *value = Value::Bytes(Vec::new()),
             },
             TransformKind::CountryCode => match value {
                 Value::Bytes(b) if !b.is_empty() => {
-                    *value = Value::Bytes(CountryCode().fake_with_rng::<String, _>(rng).into())
+                    *value = Value::Bytes(
+                        localized!(locale, |l| CountryCode(l).fake_with_rng::<String, _>(rng))
+                            .into(),
+                    )
                 }
                 Value::Bytes(_) => {}
                 _ => *value = Value::Bytes(Vec::new()),
@@ -251,6 +739,53 @@ impl TransformKind {
                 Value::Bytes(_) => {}
                 _ => *value = Value::Bytes(Vec::new()),
             },
+            TransformKind::DateShift => {
+                if let Some(date) = DateTime::from_value(value) {
+                    let max_days = config.and_then(|s| s.parse().ok()).unwrap_or(30i64).abs();
+                    let days = date.days() + rng.gen_range(-max_days..=max_days);
+                    *value = date.with_days(days).to_value();
+                }
+            }
+            TransformKind::DateTruncate => {
+                if let Some(date) = DateTime::from_value(value) {
+                    let unit = config
+                        .and_then(|s| s.parse().ok())
+                        .unwrap_or(DateUnit::Month);
+                    *value = date.truncate(unit).to_value();
+                }
+            }
+            TransformKind::DateRandom => {
+                if let Some(date) = DateTime::from_value(value) {
+                    let days = |s: Option<&String>, default: i64| {
+                        s.and_then(|s| DateTime::parse(s))
+                            .map_or(default, |d| d.days())
+                    };
+                    let start = days(config, 0);
+                    let end = days(config2, today());
+                    let range = start.min(end)..=start.max(end);
+                    *value = date.with_days(rng.gen_range(range)).to_value();
+                }
+            }
+            TransformKind::NoisePercent => map_number(value, column_type, |n| {
+                let percent = config.and_then(|s| s.parse().ok()).unwrap_or(10f64).abs();
+                let noise = n.value() * rng.gen_range(-percent..=percent) / 100.0;
+                n.with_noise(noise)
+            }),
+            TransformKind::NoiseGaussian => map_number(value, column_type, |n| {
+                let std_dev = config.and_then(|s| s.parse().ok()).unwrap_or(1f64).abs();
+                n.with_noise(std_dev * standard_normal(rng))
+            }),
+            TransformKind::Round => map_number(value, column_type, |n| {
+                let size = config
+                    .and_then(|s| s.parse::<Decimal>().ok())
+                    .filter(Decimal::is_positive)
+                    .unwrap_or(Decimal::integer(1));
+                n.round_to(size)
+            }),
+            TransformKind::Clamp => map_number(value, column_type, |n| {
+                let bound = |s: Option<&String>| s.and_then(|s| s.parse().ok());
+                n.clamp(bound(config), bound(config2))
+            }),
             TransformKind::RandomInt => match value {
                 Value::Bytes(b) if !b.is_empty() => {
                     let r = match config {
@@ -294,19 +829,18 @@ fn random_alphanum_lower(rng: &mut impl Rng, len: usize) -> String {
         .to_ascii_lowercase()
 }
 
-fn hash_email(b: &[u8]) -> String {
-    format!("{}@example", hash_to_charset(b, 11, ALPHANUM_LOWER))
+fn hash_email(key: Option<&HashKey>, b: &[u8]) -> String {
+    format!("{}@example", hash_to_charset(key, b, 11, ALPHANUM_LOWER))
 }
 
-fn hash_domain(b: &[u8]) -> String {
-    format!("{}.example", hash_to_charset(b, 6, ALPHANUM_LOWER))
+fn hash_domain(key: Option<&HashKey>, b: &[u8]) -> String {
+    format!("{}.example", hash_to_charset(key, b, 6, ALPHANUM_LOWER))
 }
 
-fn hash_to_charset(b: &[u8], len: usize, charset: &str) -> String {
+fn hash_to_charset(key: Option<&HashKey>, b: &[u8], len: usize, charset: &str) -> String {
     let charset_len = charset.len() as u8;
 
-    xxh3::xxh3_128(b)
-        .to_le_bytes()
+    hash(key, b)
         .iter()
         .take(len)
         .map(|b| *b % charset_len)
@@ -319,9 +853,54 @@ fn hash_to_charset(b: &[u8], len: usize, charset: &str) -> String {
         .collect()
 }
 
+/// The text of a value other than NULL or an empty string, or an error when it isn't utf8.
+fn as_text(value: &Value) -> Option<Result<&str, std::str::Utf8Error>> {
+    match value {
+        Value::Bytes(b) if !b.is_empty() => Some(from_utf8(b)),
+        Value::Bytes(_) | Value::NULL => None,
+        _ => Some(Ok("")),
+    }
+}
+
+/// Replace a number with `f` of it, read as a number of a column of `column_type` when there is
+/// one. The value is left as it was read when `f` doesn't change the number, and when it isn't a
+/// number.
+fn map_number(
+    value: &mut Value,
+    column_type: Option<ColumnType>,
+    f: impl FnOnce(Number) -> Number,
+) {
+    let number = match column_type {
+        Some(column_type) => Number::from_column(value, column_type),
+        None => Number::from_value(value),
+    };
+    if let Some(n) = number {
+        let new = f(n);
+        if new != n {
+            *value = new.to_value();
+        }
+    }
+}
+
+/// A sample of the normal distribution with mean 0 and standard deviation 1, by the Box-Muller
+/// transform.
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+/// Days since 1970-01-01 of the current date, in UTC.
+fn today() -> i64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    (now / 86400) as i64
+}
+
 fn parse_range<T>(s: &str) -> Result<Range<T>>
 where
-    T: PartialOrd<T> + FromStr + Sub + Sub<Output = T> + Copy,
+    T: PartialOrd<T> + FromStr + Default + Copy,
     <T as FromStr>::Err: Sync + Send + std::error::Error + 'static,
 {
     if let Some((a, b)) = s.splitn(2, '-').collect_tuple() {
@@ -330,7 +909,7 @@ where
         Ok(start..end)
     } else {
         let end = s.parse()?;
-        Ok((end - end)..end)
+        Ok(T::default()..end)
     }
 }
 
@@ -339,9 +918,10 @@ fn regex_replace(
     maybe_pattern: Option<&String>,
     maybe_replace: Option<&String>,
 ) {
-    let s = match value {
-        Value::Bytes(b) => String::from_utf8(b.to_vec()).unwrap_or("".to_owned()),
-        _ => String::new(),
+    // Matching on bytes works for strings in any character set, not only utf8.
+    let b = match value {
+        Value::Bytes(b) => b.as_slice(),
+        _ => &[],
     };
 
     let pattern = match maybe_pattern {
@@ -355,6 +935,362 @@ fn regex_replace(
     };
 
     let re = Regex::new(pattern).expect("invalid regex");
-    let new = re.replace_all(&s, replace);
-    *value = Value::Bytes(new.to_string().into());
+    let new = re.replace_all(b, replace.as_bytes()).into_owned();
+    *value = Value::Bytes(new);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mysql::consts::ColumnType::{
+        MYSQL_TYPE_DATETIME, MYSQL_TYPE_DOUBLE, MYSQL_TYPE_FLOAT, MYSQL_TYPE_LONGLONG,
+        MYSQL_TYPE_NEWDECIMAL, MYSQL_TYPE_TIMESTAMP, MYSQL_TYPE_VAR_STRING,
+    };
+
+    fn transform(yaml: &str) -> Transform {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn apply(t: &Transform, value: &str, column_type: ColumnType) -> String {
+        apply_seeded(t, value, column_type, 0)
+    }
+
+    fn apply_seeded(t: &Transform, value: &str, column_type: ColumnType, seed: u64) -> String {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut value = Value::Bytes(value.into());
+        t.apply(&mut rng, None, &mut value, None, column_type, None)
+            .unwrap();
+        String::from_utf8(as_bytes(value)).unwrap()
+    }
+
+    fn as_bytes(value: Value) -> Vec<u8> {
+        match value {
+            Value::Bytes(b) => b,
+            v => panic!("not bytes: {v:?}"),
+        }
+    }
+
+    #[test]
+    fn numbers_are_unchanged_without_bounds() {
+        let clamp = transform("{column: n, kind: clamp}");
+        for n in [
+            "12345678901234.5678",
+            "-9999999999999999.99",
+            "18446744073709551615",
+            "+1.50",
+            "1.0E+3",
+        ] {
+            assert_eq!(apply(&clamp, n, MYSQL_TYPE_NEWDECIMAL), n);
+        }
+
+        let clamp = transform("{column: n, kind: clamp, config: '-1.5', config2: 100}");
+        assert_eq!(apply(&clamp, "-3.25", MYSQL_TYPE_NEWDECIMAL), "-1.50");
+        assert_eq!(
+            apply(&clamp, "18446744073709551615", MYSQL_TYPE_LONGLONG),
+            "100"
+        );
+    }
+
+    #[test]
+    fn float_columns_get_noise_finer_than_their_digits() {
+        let percent = transform("{column: n, kind: noise_percent, config: 10}");
+        let gaussian = transform("{column: n, kind: noise_gaussian, config: 0.01}");
+        for seed in 0..8 {
+            let noisy = apply_seeded(&percent, "3", MYSQL_TYPE_DOUBLE, seed);
+            let n: f64 = noisy.parse().unwrap();
+            assert!(n != 3.0 && (2.7..=3.3).contains(&n), "{noisy}");
+
+            let noisy = apply_seeded(&gaussian, "51.5", MYSQL_TYPE_FLOAT, seed);
+            assert_ne!(noisy, "51.5");
+            assert!(noisy.parse::<f32>().is_ok(), "{noisy}");
+        }
+
+        // Decimal columns keep their scale.
+        assert_eq!(apply(&gaussian, "51.5", MYSQL_TYPE_NEWDECIMAL), "51.5");
+    }
+
+    #[test]
+    fn masks_keep_the_ends_and_separators() {
+        let mask = |options: &str, value: &str| {
+            let t = transform(&format!("{{column: c, kind: mask, mask: {{{options}}}}}"));
+            apply(&t, value, MYSQL_TYPE_VAR_STRING)
+        };
+
+        assert_eq!(mask("", "4242424242424242"), "************4242");
+        assert_eq!(
+            mask("keep_separators: true", "4242-4242-4242-4242"),
+            "****-****-****-4242"
+        );
+        assert_eq!(
+            mask("keep_prefix: 2, keep_suffix: 2, mask_char: '#'", "abcdef"),
+            "ab##ef"
+        );
+
+        // A run of hidden characters, with the separators inside it, becomes mask_length masks.
+        assert_eq!(
+            mask(
+                "keep_separators: true, mask_length: 3",
+                "4242-4242-4242-4242"
+            ),
+            "***-4242"
+        );
+        assert_eq!(
+            mask("keep_prefix: 1, mask_length: 2", "secret-value"),
+            "s**alue"
+        );
+        assert_eq!(mask("mask_length: 0", "secret"), "cret");
+
+        assert_eq!(mask("keep_separators: true", "--"), "--");
+        assert_eq!(mask("keep_separators: true, mask_length: 3", "- -"), "- -");
+
+        // Keeping every character would reveal the value, so all of it is hidden.
+        assert_eq!(mask("", "1234"), "****");
+        assert_eq!(mask("", "12345"), "*2345");
+        assert_eq!(mask("keep_prefix: 2, keep_suffix: 2", "abcd"), "****");
+        assert_eq!(mask("keep_separators: true, mask_length: 2", "12-34"), "**");
+    }
+
+    #[test]
+    fn identifiers_are_never_passed_through() {
+        for (kind, original) in [
+            ("credit_card", b"4111".to_vec()),
+            ("credit_card", b"4111\xff1111".to_vec()),
+            ("iban", b"DE89 3704 \xff 0532".to_vec()),
+            ("ssn", b"\xff".to_vec()),
+        ] {
+            let t = transform(&format!("{{column: id, kind: {kind}}}"));
+            let mut rng = StdRng::seed_from_u64(0);
+            let mut value = Value::Bytes(original.clone());
+            t.apply(
+                &mut rng,
+                None,
+                &mut value,
+                None,
+                MYSQL_TYPE_VAR_STRING,
+                None,
+            )
+            .unwrap();
+            assert_ne!(as_bytes(value), original, "{kind}");
+        }
+    }
+
+    #[test]
+    fn dates_stay_within_the_column_type() {
+        let random = transform(
+            "{column: at, kind: date_random, config: '1900-01-01', config2: '1900-12-31'}",
+        );
+        assert_eq!(
+            apply(&random, "2000-01-01 12:00:00", MYSQL_TYPE_TIMESTAMP),
+            "1970-01-02 12:00:00"
+        );
+        assert!(apply(&random, "2000-01-01 12:00:00", MYSQL_TYPE_DATETIME).starts_with("1900-"));
+
+        let shift = transform("{column: at, kind: date_shift, config: 10000}");
+        let shifted: Vec<String> = (0..20)
+            .map(|seed| apply_seeded(&shift, "2038-01-01 00:00:00", MYSQL_TYPE_TIMESTAMP, seed))
+            .collect();
+        assert!(shifted.iter().all(|s| s.as_str() <= "2038-01-17 00:00:00"));
+        assert!(shifted.iter().any(|s| s == "2038-01-17 00:00:00"));
+
+        let truncate = transform("{column: at, kind: date_truncate, config: year}");
+        assert_eq!(
+            apply(&truncate, "1970-06-01 00:00:00", MYSQL_TYPE_TIMESTAMP),
+            "1970-01-02 00:00:00"
+        );
+    }
+
+    #[test]
+    fn french_and_german_data_is_localized() {
+        let fake = |kind: &str, locale: &str, seed: u64| {
+            let t = transform(&format!("{{column: c, kind: {kind}, locale: {locale}}}"));
+            apply_seeded(&t, "x", MYSQL_TYPE_VAR_STRING, seed)
+        };
+
+        for seed in 0..20 {
+            let street = fake("addr1", "fr_fr", seed);
+            let number = street.split(' ').next().unwrap();
+            assert!(number.parse::<u8>().is_ok(), "{street}");
+            assert!(
+                [
+                    "rue",
+                    "avenue",
+                    "boulevard",
+                    "place",
+                    "allée",
+                    "impasse",
+                    "chemin",
+                    "quai"
+                ]
+                .iter()
+                .any(|t| street.contains(&format!(" {t} "))),
+                "{street}"
+            );
+            let phone = fake("phone", "fr_fr", seed);
+            assert!(
+                phone.starts_with('0') || phone.starts_with("+33"),
+                "{phone}"
+            );
+            assert_eq!(fake("postal_code", "fr_fr", seed).len(), 5);
+            assert_eq!(fake("state_code", "fr_fr", seed).len(), 2);
+
+            let street = fake("addr1", "de_de", seed);
+            let number = street.rsplit(' ').next().unwrap();
+            assert!(number.parse::<u8>().is_ok(), "{street}");
+            let phone = fake("phone", "de_de", seed);
+            assert!(
+                phone.starts_with('0') || phone.starts_with("+49"),
+                "{phone}"
+            );
+            let company = fake("organization", "de_de", seed);
+            assert!(
+                ["GmbH", "AG", "KG", "OHG", "e.K."]
+                    .iter()
+                    .any(|s| company.ends_with(s)),
+                "{company}"
+            );
+        }
+    }
+
+    #[test]
+    fn domains_give_equal_values_the_same_fake_across_tables() {
+        let users = transform("{column: name, kind: fullname, domain: person}");
+        let orders = transform("{column: customer_name, kind: fullname, domain: person}");
+
+        // Each table has its own generator, so different seeds stand in for different tables.
+        let in_users = apply_seeded(&users, "Ada Lovelace", MYSQL_TYPE_VAR_STRING, 1);
+        let in_orders = apply_seeded(&orders, "Ada Lovelace", MYSQL_TYPE_VAR_STRING, 2);
+        assert_eq!(in_users, in_orders);
+        assert_ne!(in_users, "Ada Lovelace");
+
+        let others: Vec<String> = ["Alan Turing", "Grace Hopper", "Edsger Dijkstra"]
+            .iter()
+            .map(|name| apply_seeded(&orders, name, MYSQL_TYPE_VAR_STRING, 2))
+            .collect();
+        assert!(others.iter().any(|name| *name != in_users), "{others:?}");
+
+        let other_domain = transform("{column: name, kind: fullname, domain: employee}");
+        let fakes: Vec<String> = ["Ada Lovelace", "Alan Turing", "Grace Hopper"]
+            .iter()
+            .map(|name| apply_seeded(&other_domain, name, MYSQL_TYPE_VAR_STRING, 1))
+            .collect();
+        let same_domain: Vec<String> = ["Ada Lovelace", "Alan Turing", "Grace Hopper"]
+            .iter()
+            .map(|name| apply_seeded(&users, name, MYSQL_TYPE_VAR_STRING, 1))
+            .collect();
+        assert_ne!(fakes, same_domain);
+    }
+
+    #[test]
+    fn hash_keys_change_hashed_values() {
+        let hashed = |kind: &str, key: Option<&HashKey>| {
+            let t = transform(&format!("{{column: c, kind: {kind}}}"));
+            let mut rng = StdRng::seed_from_u64(0);
+            let mut value = Value::Bytes(b"ada@example.com".to_vec());
+            t.apply(&mut rng, key, &mut value, None, MYSQL_TYPE_VAR_STRING, None)
+                .unwrap();
+            String::from_utf8(as_bytes(value)).unwrap()
+        };
+        let key = HashKey::new(Some("secret"), None).unwrap().unwrap();
+        let other_key = HashKey::new(Some("another secret"), None).unwrap().unwrap();
+
+        for kind in ["email_hash", "domain_hash"] {
+            let unkeyed = hashed(kind, None);
+            let keyed = hashed(kind, Some(&key));
+            assert_ne!(keyed, unkeyed, "{kind}");
+            assert_ne!(keyed, hashed(kind, Some(&other_key)), "{kind}");
+            assert_eq!(keyed, hashed(kind, Some(&key)), "{kind}");
+            assert_eq!(
+                keyed,
+                hashed(
+                    kind,
+                    HashKey::new(Some(" secret\n"), None).unwrap().as_ref()
+                ),
+                "{kind}"
+            );
+        }
+    }
+
+    #[test]
+    fn hash_keys_must_be_given_once_and_not_empty() {
+        let path = std::env::temp_dir().join(format!("hash-key-{}", std::process::id()));
+        std::fs::write(&path, "secret\n").unwrap();
+
+        let from_file = HashKey::new(None, Some(&path)).unwrap().unwrap();
+        assert_eq!(from_file.0, b"secret");
+
+        let both = HashKey::new(Some("secret"), Some(&path)).unwrap_err();
+        assert!(both.to_string().contains("only one"), "{both}");
+
+        std::fs::write(&path, "\n").unwrap();
+        let empty_file = HashKey::new(None, Some(&path)).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(empty_file.to_string(), "hash key is empty");
+
+        let empty = HashKey::new(Some("  "), None).unwrap_err();
+        assert_eq!(empty.to_string(), "hash key is empty");
+        assert!(HashKey::new(None, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn unique_transforms_regenerate_until_exhausted() {
+        let unique_values = UniqueValues::default();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut unique = |t: &Transform, original: &str| {
+            let mut value = Value::Bytes(original.into());
+            t.apply(
+                &mut rng,
+                None,
+                &mut value,
+                None,
+                MYSQL_TYPE_VAR_STRING,
+                Some((&unique_values, "db.t.c")),
+            )
+            .map(|_| String::from_utf8(as_bytes(value)).unwrap())
+        };
+
+        // Two values to choose from; the second row regenerates until it gets the other one.
+        let two = transform("{column: c, kind: random_int, config: 1-3, unique: true}");
+        let first = unique(&two, "x").unwrap();
+        let second = unique(&two, "x").unwrap();
+        assert_ne!(first, second);
+        let err = unique(&two, "x").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no unique value for db.t.c after 100 attempts; the transform may not have enough values"
+        );
+
+        // A domain transform gives the same original the same value, which isn't a collision.
+        let keyed = transform("{column: c, kind: firstname, domain: names, unique: true}");
+        let ada = unique(&keyed, "Ada").unwrap();
+        assert_eq!(unique(&keyed, "Ada").unwrap(), ada);
+    }
+
+    #[test]
+    fn unique_replace_fails_on_its_second_row() {
+        let unique_values = UniqueValues::default();
+        let replace = transform("{column: c, kind: replace, config: same, unique: true}");
+        let set = replace.unique_set("db", "t").unwrap();
+        assert_eq!(set, "db.t.c");
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut apply = |original: &str| {
+            let mut value = Value::Bytes(original.into());
+            replace.apply(
+                &mut rng,
+                None,
+                &mut value,
+                None,
+                MYSQL_TYPE_VAR_STRING,
+                Some((&unique_values, &set)),
+            )
+        };
+        assert!(apply("a").is_ok());
+        assert!(apply("b").is_err());
+
+        // Values are compared without case and NULL is never a duplicate.
+        assert!(unique_values.insert("other", &Value::Bytes(b"Same".to_vec()), None));
+        assert!(!unique_values.insert("other", &Value::Bytes(b"SAME".to_vec()), None));
+        assert!(unique_values.insert("other", &Value::NULL, None));
+        assert!(unique_values.insert("other", &Value::NULL, None));
+    }
 }